@@ -1,59 +1,165 @@
-use crate::config::Configuration;
-use crate::pull_request::PullRequest;
+use crate::config;
+use crate::config::{Configuration, WhenFull};
+use crate::github;
+use crate::pull_request::{Options, PullRequest, Source};
 use crate::runner::Runner;
-use crate::REPOS;
+use crate::util::unix_timestamp;
+use crate::{DISMISSED, REPOS};
 use anyhow::{anyhow, Context, Result};
 use clokwerk::ScheduleHandle;
 use clokwerk::{Scheduler, TimeUnits};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
 use nix::unistd::Uid;
+use octocrab::Octocrab;
 use std::fs;
-use std::io::{BufRead, BufReader, BufWriter};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::os::unix::io::AsRawFd;
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use tokio::runtime::Runtime;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::runtime::Handle;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Notify, RwLock};
 use tokio::task;
 
-use super::{FailureKind, Message, Request, RequestBody, Response, PROTOCOL_VERSION};
+use super::{
+    Entry, Envelope, FailureKind, ForceOutcome, ItemResult, Message, PopOutcome, PushOutcome,
+    Request, RequestBody, Response, Run, Status, PROTOCOL_VERSION,
+};
 
 /// Represents the state in a running agent.
 pub struct Agent {
     /// The local path to the Unix domain socket.
     agent_path: PathBuf,
 
+    /// The lock file guarding against a second agent, held until the agent is dropped.
+    lock: File,
+
+    /// Whether the configuration file is watched and reloaded on change.
+    watch_config: bool,
+
+    /// State shared with the tasks serving individual clients.
+    shared: Arc<Shared>,
+}
+
+/// Bookkeeping of queue runs, reported through `RequestBody::Status`.
+/// Also makes sure only a single run happens at a time.
+#[derive(Default)]
+struct RunState {
+    last_run: Option<Run>,
+    next_run: Option<u64>,
+
+    /// Whether a run is in progress.
+    running: bool,
+
+    /// Whether another run was requested while one was in progress.
+    follow_up: bool,
+}
+
+impl RunState {
+    /// Marks a run as started, returning `false` if one is in progress already.
+    fn try_start(&mut self) -> bool {
+        if self.running {
+            return false;
+        }
+
+        self.running = true;
+        true
+    }
+
+    /// Starts a run, or queues a single follow-up run if one is in progress.
+    fn force(&mut self) -> ForceOutcome {
+        if self.try_start() {
+            ForceOutcome::Started
+        } else if self.follow_up {
+            ForceOutcome::MergedIntoFollowUp
+        } else {
+            self.follow_up = true;
+            ForceOutcome::FollowUpQueued
+        }
+    }
+
+    /// Ends the current run, returning `true` if a follow-up run takes its place.
+    fn finish(&mut self) -> bool {
+        if self.follow_up {
+            self.follow_up = false;
+            return true;
+        }
+
+        self.running = false;
+        false
+    }
+}
+
+/// State shared between the agent and its client connections.
+struct Shared {
+    /// The configuration read from a file, swapped out on reload.
+    configuration: RwLock<Configuration>,
+
     /// The scheduler
-    scheduler: Option<ScheduleHandle>,
+    scheduler: Mutex<Option<ScheduleHandle>>,
+
+    /// Notified once a client asked the agent to quit.
+    quit: Notify,
+
+    /// When the agent was started.
+    started_at: Instant,
 
-    /// Whether or not the agent intends to quit momentarily.
-    quitting: bool,
+    /// The last and next runs of the queue process.
+    run_state: Mutex<RunState>,
 
-    /// The configuration read from a file
-    configuration: Configuration,
+    /// Held for reading by every queue run, shutdown takes it for writing
+    /// to wait for runs that are still in flight.
+    runs: RwLock<()>,
 }
 
 impl Agent {
     /// Initializes a new agent without accepting connections.
     pub fn new(configuration: Configuration) -> Result<Self> {
         let agent_path = Self::path();
+        let lock = Self::lock(&Self::lock_path())?;
 
         if agent_path.exists() {
-            return Err(anyhow!(
-                "an agent is already running or didn't exit cleanly"
-            ));
+            if Self::alive(&agent_path) {
+                return Err(anyhow!("an agent is already running"));
+            }
+
+            log::warn!("Removing stale agent socket {}", agent_path.display());
+            fs::remove_file(&agent_path)?;
         }
 
-        #[allow(clippy::redundant_field_names)]
+        let pull_requests = crate::state::load()?;
+        log::info!("Restored {} queued pull requests", pull_requests.len());
+        *REPOS.blocking_lock() = pull_requests;
+        *DISMISSED.blocking_lock() = crate::state::load_dismissed()?;
+
         Ok(Self {
             agent_path,
-            quitting: false,
-            scheduler: None,
-            configuration: configuration,
+            lock,
+            watch_config: false,
+            shared: Arc::new(Shared {
+                configuration: RwLock::new(configuration),
+                scheduler: Mutex::new(None),
+                started_at: Instant::now(),
+                run_state: Mutex::new(RunState::default()),
+                quit: Notify::new(),
+                runs: RwLock::new(()),
+            }),
         })
     }
 
+    /// Reload the configuration whenever its file changes.
+    pub fn watch_config(&mut self, enabled: bool) {
+        self.watch_config = enabled;
+    }
+
     pub fn path() -> PathBuf {
         let mut agent_path = PathBuf::from("/tmp");
         agent_path.push(format!(
@@ -65,42 +171,425 @@ impl Agent {
         agent_path
     }
 
-    fn setup_process(&mut self) -> Result<()> {
-        let mut scheduler = Scheduler::new();
-        let configuration: Configuration = self.configuration.clone();
+    /// The path of the lock file that holds the pid of the running agent.
+    pub fn lock_path() -> PathBuf {
+        Self::path().with_extension("lock")
+    }
+
+    /// Returns whether an agent is accepting connections on the socket at `path`.
+    /// A socket left behind by a crashed agent refuses connections.
+    fn alive(path: &Path) -> bool {
+        std::os::unix::net::UnixStream::connect(path).is_ok()
+    }
+
+    /// Takes an exclusive `flock` on the file at `path`, failing if another agent holds it.
+    fn lock(path: &Path) -> Result<File> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open lock file {}", path.display()))?;
 
-        let scheduler_interval = (self.configuration.queue.interval_in_minutes as u32).minutes();
+        if let Err(err) = flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            if err == Errno::EWOULDBLOCK {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
 
-        scheduler.every(scheduler_interval).run(move || {
-            // TODO: Fix double clone
-            Self::run_process(configuration.clone(), false);
+                return Err(anyhow!("an agent is already running (pid {})", pid.trim()));
+            }
+
+            return Err(anyhow!("failed to lock {}: {}", path.display(), err));
+        }
+
+        Ok(file)
+    }
+
+    /// Records the pid of the current process in the held lock file.
+    /// Called once the agent runs, as daemonizing changes the pid.
+    fn write_pid(&mut self) -> Result<()> {
+        self.lock.set_len(0)?;
+        self.lock.seek(SeekFrom::Start(0))?;
+        write!(self.lock, "{}", std::process::id())?;
+        self.lock.flush()?;
+
+        Ok(())
+    }
+
+    /// Runs the queue process every `interval_in_minutes`,
+    /// replacing and stopping a previously started scheduler.
+    async fn schedule(shared: &Arc<Shared>, interval_in_minutes: u32) -> Result<()> {
+        let mut scheduler = Scheduler::new();
+        let process_shared = shared.clone();
+        let handle = Handle::current();
+        let interval = u64::from(interval_in_minutes) * 60;
+
+        scheduler.every(interval_in_minutes.minutes()).run(move || {
+            process_shared.run_state.lock().unwrap().next_run = Some(unix_timestamp() + interval);
+            handle.block_on(Self::run_process(process_shared.clone()));
         });
 
+        shared.run_state.lock().unwrap().next_run = Some(unix_timestamp() + interval);
+
         let thread_handle = scheduler.watch_thread(Duration::from_millis(1000));
-        self.scheduler = Some(thread_handle);
+        let previous = shared.scheduler.lock().unwrap().replace(thread_handle);
+
+        if let Some(previous) = previous {
+            // Stopping joins the scheduler thread, which may be blocked on a queue run.
+            task::spawn_blocking(move || previous.stop()).await?;
+        }
 
         Ok(())
     }
 
-    fn run_process(configuration: Configuration, forced: bool) {
+    /// Loads and validates the configuration file and swaps it in for later runs.
+    async fn reload(shared: &Arc<Shared>) -> Result<()> {
+        let configuration = crate::config::load()?;
+        crate::config::validate(&configuration)?;
+
+        let mut queue = REPOS.lock().await;
+        let evicted = Self::trim(&mut queue, &configuration.queue);
+        if !evicted.is_empty() {
+            log::info!(
+                "Queue exceeds the new limit, evicted {:?}",
+                evicted.iter().map(|p| &p.url).collect::<Vec<_>>()
+            );
+            crate::state::persist(&queue);
+        }
+        drop(queue);
+
+        let interval_in_minutes = configuration.queue.interval_in_minutes;
+        let previous = std::mem::replace(&mut *shared.configuration.write().await, configuration);
+        Self::unlabel(shared, evicted).await;
+
+        if previous.queue.interval_in_minutes != interval_in_minutes {
+            Self::schedule(shared, interval_in_minutes).await?;
+        }
+
+        log::info!("Configuration reloaded");
+
+        Ok(())
+    }
+
+    /// Reloads the configuration whenever the modification time of its file changes.
+    async fn watch(shared: Arc<Shared>) {
+        let path = crate::config::config_path();
+        let modified = || fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let mut last_modified = modified();
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            let current = modified();
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            if let Err(err) = Self::reload(&shared).await {
+                log::error!("Failed to reload configuration: {}", err);
+            }
+        }
+    }
+
+    /// Runs the queue process unless a run is in progress already,
+    /// in which case the scheduled run is covered by the one in progress.
+    async fn run_process(shared: Arc<Shared>) {
+        if !shared.run_state.lock().unwrap().try_start() {
+            log::info!("Queue process already running, skipping scheduled run");
+            return;
+        }
+
+        Self::run_until_idle(shared).await;
+    }
+
+    /// Starts a run for `RequestBody::ForceProcess`,
+    /// queueing a follow-up run instead if one is in progress.
+    fn force_process(shared: &Arc<Shared>) -> ForceOutcome {
+        let outcome = shared.run_state.lock().unwrap().force();
+
+        if outcome == ForceOutcome::Started {
+            task::spawn(Self::run_until_idle(shared.clone()));
+        }
+
+        outcome
+    }
+
+    /// Runs the queue process for a run marked as started,
+    /// followed by any follow-up run queued in the meantime.
+    async fn run_until_idle(shared: Arc<Shared>) {
+        Self::run_repeatedly(
+            shared,
+            |shared| async move { Self::run_once(&shared).await },
+        )
+        .await;
+    }
+
+    /// Calls `run` until no follow-up run is queued. Each run is a task of its own,
+    /// so a panicking run is recorded as failed instead of leaving a run in progress for good.
+    async fn run_repeatedly<F, Fut>(shared: Arc<Shared>, run: F)
+    where
+        F: Fn(Arc<Shared>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let _run = shared.runs.read().await;
+
+        loop {
+            if let Err(err) = task::spawn(run(shared.clone())).await {
+                log::error!("Queue process panicked: {}", err);
+
+                if let Some(run) = shared.run_state.lock().unwrap().last_run.as_mut() {
+                    run.finished_at = Some(unix_timestamp());
+                    run.result = Some(Err(format!("panicked: {}", err)));
+                }
+            }
+
+            if !shared.run_state.lock().unwrap().finish() {
+                break;
+            }
+
+            log::info!("Starting queued follow-up run");
+        }
+    }
+
+    async fn run_once(shared: &Shared) {
         log::info!("Starting queue process");
 
-        let closure = || async {
-            let mut runner = Runner::new(configuration).unwrap();
+        shared.run_state.lock().unwrap().last_run = Some(Run {
+            started_at: unix_timestamp(),
+            finished_at: None,
+            result: None,
+        });
 
-            let pull_requests = crate::REPOS.lock().unwrap().to_vec();
+        let result = Self::process_queue(shared).await;
+        if let Err(err) = &result {
+            log::error!("Queue process failed: {}", err);
+        }
 
-            let _ = runner.process(pull_requests).await;
+        if let Some(run) = shared.run_state.lock().unwrap().last_run.as_mut() {
+            run.finished_at = Some(unix_timestamp());
+            run.result = Some(result.map_err(|err| err.to_string()));
+        }
+    }
+
+    async fn process_queue(shared: &Shared) -> Result<()> {
+        let configuration = shared.configuration.read().await.clone();
+
+        // Polling watched repositories must not keep the queue from being processed.
+        if let Err(err) = Self::discover(&configuration).await {
+            log::error!("Polling watched repositories failed: {}", err);
+        }
+
+        let mut runner = Runner::new(configuration.clone())?;
+
+        let pull_requests = REPOS.lock().await.to_vec();
+
+        let result = runner.process(pull_requests).await;
+
+        let mut locked = REPOS.lock().await;
+        runner.cleanup(&mut locked);
+        crate::state::persist(&locked);
+
+        // Dropped pull requests would fail again if found in a watched repository.
+        if !runner.dropped().is_empty() {
+            let mut dismissed = DISMISSED.lock().await;
+            dismissed.extend(runner.dropped().iter().cloned());
+            crate::state::persist_dismissed(&dismissed);
+        }
+        drop(locked);
+
+        Self::remove_merging_label(&configuration, runner.removed()).await;
+
+        result
+    }
+
+    /// Queues the open pull requests found in watched repositories or labeled for the queue,
+    /// and drops the ones queued through a label that has since been removed.
+    async fn discover(configuration: &Configuration) -> Result<()> {
+        if !configuration.discovers() {
+            // Nothing is queued on its own, so nothing needs to be kept out of the queue.
+            let mut dismissed = DISMISSED.lock().await;
+            if !dismissed.is_empty() {
+                dismissed.clear();
+                crate::state::persist_dismissed(&dismissed);
+            }
+
+            return Ok(());
+        }
+
+        let octocrab = github::client()?;
+        let mut found = Vec::new();
+        for watch in &configuration.watch {
+            for query in github::watch_queries(watch) {
+                found.extend(Self::search(&octocrab, &query, Source::Watch).await?);
+            }
+        }
+
+        // A failed label search keeps the pull requests found in watched repositories,
+        // but can't tell which labels were removed.
+        let labeled = match &configuration.labels {
+            Some(labels) => {
+                let query = github::label_query(labels);
+                match Self::search(&octocrab, &query, Source::Label).await {
+                    Ok(labeled) => Some(labeled),
+                    Err(err) => {
+                        log::error!("Searching labeled pull requests failed: {}", err);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let complete = configuration.labels.is_none() || labeled.is_some();
 
-            runner.cleanup(&mut crate::REPOS.lock().unwrap());
+        // The queue is only locked once all searches are done, so clients aren't held up.
+        let mut queue = REPOS.lock().await;
+        let mut dismissed = DISMISSED.lock().await;
+        let unlabeled = match &labeled {
+            Some(labeled) => Self::drop_unlabeled(&mut queue, labeled),
+            None => Vec::new(),
         };
+        found.extend(labeled.unwrap_or_default());
 
-        if forced {
-            task::spawn(closure());
-        } else {
-            let rt = Runtime::new().unwrap();
+        if complete {
+            Self::forget_dismissed(&mut dismissed, &found);
+        }
+        let added = Self::enqueue_discovered(&mut queue, &dismissed, found, &configuration.queue);
+
+        log::info!(
+            "Queued {} and dropped {} unlabeled pull requests",
+            added.len(),
+            unlabeled.len()
+        );
+        if !added.is_empty() || !unlabeled.is_empty() {
+            crate::state::persist(&queue);
+        }
+        crate::state::persist_dismissed(&dismissed);
+        drop(dismissed);
+        drop(queue);
+
+        if let Some(merging) = configuration
+            .labels
+            .as_ref()
+            .and_then(|l| l.merging.as_ref())
+        {
+            for pull_request in added.iter().filter(|p| p.source == Source::Label) {
+                if let Err(err) = github::add_label(&octocrab, pull_request, merging).await {
+                    log::warn!("Failed to label {:?}: {}", pull_request.url, err);
+                }
+            }
+        }
+        Self::remove_merging_label(configuration, &unlabeled).await;
+
+        Ok(())
+    }
+
+    async fn search(octocrab: &Octocrab, query: &str, source: Source) -> Result<Vec<PullRequest>> {
+        let results = github::search_pull_requests(octocrab, query).await?;
+
+        Ok(results
+            .into_iter()
+            .map(|result| PullRequest {
+                source,
+                queued_at: Some(unix_timestamp()),
+                ..result.pull_request
+            })
+            .collect())
+    }
+
+    /// Removes the pull requests queued through their label that no longer carry it.
+    fn drop_unlabeled(queue: &mut Vec<PullRequest>, labeled: &[PullRequest]) -> Vec<PullRequest> {
+        let (unlabeled, kept) = queue.drain(..).partition(|queued: &PullRequest| {
+            queued.source == Source::Label && !labeled.iter().any(|other| other.same(queued))
+        });
+        *queue = kept;
+
+        unlabeled
+    }
+
+    /// Removes the `merging` label from the pull requests queued through their label.
+    async fn remove_merging_label(configuration: &Configuration, pull_requests: &[PullRequest]) {
+        let merging = match configuration
+            .labels
+            .as_ref()
+            .and_then(|l| l.merging.as_ref())
+        {
+            Some(merging) => merging,
+            None => return,
+        };
+
+        let labeled: Vec<&PullRequest> = pull_requests
+            .iter()
+            .filter(|pull_request| pull_request.source == Source::Label)
+            .collect();
+        if labeled.is_empty() {
+            return;
+        }
+
+        let octocrab = match github::client() {
+            Ok(octocrab) => octocrab,
+            Err(err) => {
+                log::warn!("Failed to remove the {} label: {}", merging, err);
+                return;
+            }
+        };
+
+        for pull_request in labeled {
+            if let Err(err) = github::remove_label(&octocrab, pull_request, merging).await {
+                log::warn!("Failed to unlabel {:?}: {}", pull_request.url, err);
+            }
+        }
+    }
+
+    /// Forgets the dismissed pull requests that are no longer `found`,
+    /// as they are closed or no longer match the watch.
+    fn forget_dismissed(dismissed: &mut Vec<PullRequest>, found: &[PullRequest]) {
+        dismissed.retain(|pull_request| found.iter().any(|other| other.same(pull_request)));
+    }
+
+    /// Adds the `found` pull requests that are neither queued nor dismissed,
+    /// as long as the queue has room for them, and returns the added ones.
+    fn enqueue_discovered(
+        queue: &mut Vec<PullRequest>,
+        dismissed: &[PullRequest],
+        found: Vec<PullRequest>,
+        settings: &config::Queue,
+    ) -> Vec<PullRequest> {
+        let mut added = Vec::new();
+        for pull_request in found {
+            if dismissed.iter().any(|other| other.same(&pull_request))
+                || queue.iter().any(|queued| queued.same(&pull_request))
+            {
+                continue;
+            }
+
+            // Discovered pull requests never evict the ones pushed by hand.
+            if queue.len() >= settings.limit as usize {
+                log::warn!("Queue is full, not queueing {:?}", pull_request.url);
+                break;
+            }
+
+            queue.push(pull_request.clone());
+            added.push(pull_request);
+        }
+
+        added
+    }
+
+    async fn status(shared: &Shared) -> Status {
+        let queue_length = REPOS.lock().await.len();
+        let run_state = shared.run_state.lock().unwrap();
+        let last_run = run_state.last_run.clone();
 
-            rt.block_on(closure());
+        Status {
+            protocol: PROTOCOL_VERSION,
+            pid: std::process::id(),
+            uptime_in_seconds: shared.started_at.elapsed().as_secs(),
+            config_path: crate::config::config_path(),
+            queue_length,
+            running: run_state.running,
+            last_run,
+            next_run: run_state.next_run,
         }
     }
 
@@ -109,7 +598,7 @@ impl Agent {
     pub fn spawn() -> Result<()> {
         let agent_path = Self::path();
 
-        if agent_path.exists() {
+        if Self::alive(&agent_path) {
             return Ok(());
         }
 
@@ -132,7 +621,7 @@ impl Agent {
 
         for _attempt in 0..10 {
             thread::sleep(Duration::from_millis(10));
-            if agent_path.exists() {
+            if Self::alive(&agent_path) {
                 return Ok(());
             }
         }
@@ -140,6 +629,15 @@ impl Agent {
         Err(anyhow!("agent spawn timeout exhausted"))
     }
 
+    /// Returns whether the peer on the other end of `stream` runs as the same user as the agent.
+    fn auth_client<S: AsRawFd>(stream: &S) -> bool {
+        Self::authorized(Self::peer_uid(stream))
+    }
+
+    fn authorized(peer_uid: Option<Uid>) -> bool {
+        peer_uid == Some(Uid::effective())
+    }
+
     #[cfg(any(
         target_os = "macos",
         target_os = "freebsd",
@@ -147,134 +645,885 @@ impl Agent {
         target_os = "netbsd",
         target_os = "dragonfly",
     ))]
-    fn auth_client(&self, stream: &UnixStream) -> bool {
+    fn peer_uid<S: AsRawFd>(stream: &S) -> Option<Uid> {
         use nix::unistd;
 
-        if let Ok((peer_uid, _)) = unistd::getpeereid(stream.as_raw_fd()) {
-            peer_uid == Uid::effective()
-        } else {
-            false
-        }
+        unistd::getpeereid(stream.as_raw_fd())
+            .ok()
+            .map(|(peer_uid, _)| peer_uid)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_uid<S: AsRawFd>(stream: &S) -> Option<Uid> {
+        use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+
+        getsockopt(stream.as_raw_fd(), PeerCredentials)
+            .ok()
+            .map(|credentials| Uid::from_raw(credentials.uid()))
     }
 
     /// Handles a single client connection.
     /// Individual clients may issue multiple requests in a single session.
-    fn handle_client(&mut self, stream: UnixStream) {
-        let reader = BufReader::new(&stream);
-        let mut writer = BufWriter::new(&stream);
-
-        if !self.auth_client(&stream) {
+    async fn handle_client(shared: Arc<Shared>, stream: UnixStream) {
+        if !Self::auth_client(&stream) {
+            let (_, mut writer) = stream.into_split();
             // This can fail, but we don't care.
-            let _ = Response::Failure(FailureKind::Auth).write(&mut writer);
+            let _ = Self::respond(&mut writer, Response::Failure(FailureKind::Auth)).await;
             return;
         }
 
-        for line in reader.lines() {
-            let line = match line {
-                Ok(line) => line,
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return,
                 Err(e) => {
                     // This can fail, but we don't care.
-                    let _ = Response::Failure(FailureKind::Io(e.to_string())).write(&mut writer);
+                    let _ = Self::respond(
+                        &mut writer,
+                        Response::Failure(FailureKind::Io(e.to_string())),
+                    )
+                    .await;
                     return;
                 }
             };
 
+            let protocol = serde_json::from_str::<Envelope>(&line).map(|e| e.protocol);
+            if matches!(protocol, Ok(protocol) if protocol != PROTOCOL_VERSION) {
+                let _ = Self::respond(
+                    &mut writer,
+                    Response::Failure(FailureKind::VersionMismatch(PROTOCOL_VERSION)),
+                )
+                .await;
+                return;
+            }
+
             let req: Request = match serde_json::from_str(&line) {
                 Ok(req) => req,
                 Err(e) => {
-                    let _ =
-                        Response::Failure(FailureKind::Malformed(e.to_string())).write(&mut writer);
+                    let _ = Self::respond(
+                        &mut writer,
+                        Response::Failure(FailureKind::Malformed(e.to_string())),
+                    )
+                    .await;
                     return;
                 }
             };
 
-            if req.protocol != PROTOCOL_VERSION {
-                let _ = Response::Failure(FailureKind::VersionMismatch(PROTOCOL_VERSION))
-                    .write(&mut writer);
+            let quitting = req.body == RequestBody::Quit;
+            let resp = Self::handle_request(&shared, req.body).await;
+
+            // This can fail, but we don't care.
+            let _ = Self::respond(&mut writer, resp).await;
+
+            if quitting {
+                shared.quit.notify_one();
                 return;
             }
+        }
+    }
+
+    async fn handle_request(shared: &Arc<Shared>, body: RequestBody) -> Response {
+        match body {
+            RequestBody::Quit => Response::Success("OK".into()),
+            RequestBody::Push { urls, options } => {
+                let queue = shared.configuration.read().await.queue.clone();
+                let mut locked = REPOS.lock().await;
+                let (results, evicted) = Self::push_all(&mut locked, urls, &options, &queue);
 
-            let resp = match req.body {
-                RequestBody::Quit => {
-                    self.quitting = true;
-                    Response::Success("OK".into())
+                // Pushing a dismissed pull request by hand takes it back.
+                let mut dismissed = DISMISSED.lock().await;
+                let dismissed_before = dismissed.len();
+                dismissed
+                    .retain(|pull_request| !locked.iter().any(|queued| queued.same(pull_request)));
+                if dismissed.len() != dismissed_before {
+                    crate::state::persist_dismissed(&dismissed);
                 }
-                RequestBody::Push(url) => {
-                    match PullRequest::new(url) {
-                        Ok(pull_reqeuest) => {
-                            REPOS.lock().unwrap().push(pull_reqeuest);
-                        }
-                        Err(_) => {}
-                    }
-                    Response::Success("OK".into())
+
+                if results.iter().any(|item| {
+                    matches!(
+                        item.result,
+                        Ok(PushOutcome::Added | PushOutcome::Evicted(_) | PushOutcome::Updated)
+                    )
+                }) {
+                    crate::state::persist(&locked);
                 }
-                RequestBody::Pop(url) => {
-                    let mut locked = REPOS.lock().unwrap();
-                    if let Some(pos) = locked.iter().position(|x| {
-                        (*x).same(&PullRequest {
-                            url: Some(url.clone()),
-                            ..PullRequest::default()
-                        })
-                    }) {
-                        locked.remove(pos);
-                    }
-                    Response::Success("OK".into())
+                Self::unlabel(shared, evicted).await;
+                Response::Pushed(results)
+            }
+            RequestBody::Pop(urls) => {
+                let mut locked = REPOS.lock().await;
+                let mut dismissed = DISMISSED.lock().await;
+                let dismissed_before = dismissed.len();
+                let results: Vec<ItemResult<PopOutcome>> = urls
+                    .into_iter()
+                    .map(|url| ItemResult {
+                        result: Self::pop(&mut locked, &mut dismissed, url.clone()),
+                        url,
+                    })
+                    .collect();
+
+                if results
+                    .iter()
+                    .any(|item| item.result == Ok(PopOutcome::Removed))
+                {
+                    crate::state::persist(&locked);
+                    crate::state::persist_dismissed(&dismissed);
+                    Self::unlabel(shared, dismissed[dismissed_before..].to_vec()).await;
                 }
-                RequestBody::ForceProcess => {
-                    log::info!("Result of forced process");
+                Response::Popped(results)
+            }
+            RequestBody::ForceProcess => {
+                let outcome = Self::force_process(shared);
+                log::info!("Forced process: {:?}", outcome);
 
-                    let result = Agent::run_process(self.configuration.clone(), true);
+                Response::Forced(outcome)
+            }
+            RequestBody::Status => Response::Status(Self::status(shared).await),
+            RequestBody::Reload => match Self::reload(shared).await {
+                Ok(()) => Response::Success("OK".into()),
+                Err(err) => Response::Failure(FailureKind::Config(err.to_string())),
+            },
+            RequestBody::List => Response::List(
+                REPOS
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|pr| Entry {
+                        url: pr.url.clone().unwrap_or_default(),
+                        owner: pr.owner.clone(),
+                        repo: pr.repo.clone(),
+                        number: pr.request,
+                        state: pr.state,
+                        reason: pr.reason.clone(),
+                        queued_at: pr.queued_at,
+                        last_checked: pr.last_checked,
+                        failures: pr.failures,
+                    })
+                    .collect(),
+            ),
+            RequestBody::Clear => {
+                let mut locked = REPOS.lock().await;
+                let mut dismissed = DISMISSED.lock().await;
+                let cleared = locked.clone();
+                dismissed.extend(locked.drain(..));
+                crate::state::persist(&locked);
+                crate::state::persist_dismissed(&dismissed);
+                Self::unlabel(shared, cleared).await;
+                Response::Success("OK".into())
+            }
+        }
+    }
 
-                    log::info!("Result of forced process: {:?}", result);
+    /// Removes the `merging` label from pull requests popped, cleared or evicted outside a run
+    /// in the background, so the client doesn't wait on GitHub.
+    async fn unlabel(shared: &Arc<Shared>, pull_requests: Vec<PullRequest>) {
+        if pull_requests.is_empty() {
+            return;
+        }
 
-                    Response::Success("OK".into())
-                }
-                RequestBody::List => Response::List(
-                    REPOS
-                        .lock()
-                        .unwrap()
-                        .iter()
-                        .map(|pr| pr.url.as_ref().unwrap())
-                        .map(|v| v.to_string())
-                        .collect(),
-                ),
-                RequestBody::Clear => {
-                    REPOS.lock().unwrap().clear();
-                    Response::Success("OK".into())
+        let configuration = shared.configuration.read().await.clone();
+
+        task::spawn(async move {
+            Self::remove_merging_label(&configuration, &pull_requests).await;
+        });
+    }
+
+    /// Evicts the oldest pull requests until the queue fits a lowered limit,
+    /// if full queues evict, and returns them.
+    fn trim(queue: &mut Vec<PullRequest>, settings: &config::Queue) -> Vec<PullRequest> {
+        let limit = settings.limit as usize;
+        if settings.when_full != WhenFull::EvictOldest || queue.len() <= limit {
+            return Vec::new();
+        }
+
+        let excess = queue.len() - limit;
+        queue.drain(..excess).collect()
+    }
+
+    /// Pushes all `urls`, where a full queue only evicts the pull requests queued
+    /// before, never the ones added by the same request. Returns the evicted ones as well.
+    fn push_all(
+        queue: &mut Vec<PullRequest>,
+        urls: Vec<String>,
+        options: &Options,
+        settings: &config::Queue,
+    ) -> (Vec<ItemResult<PushOutcome>>, Vec<PullRequest>) {
+        // Evicting always takes the oldest of these, in order.
+        let queued_before = queue.clone();
+        let mut evictions = 0;
+
+        let results = urls
+            .into_iter()
+            .map(|url| {
+                let evictable = evictions < queued_before.len();
+                let result = Self::push(queue, url.clone(), options, settings, evictable);
+                if let Ok(PushOutcome::Evicted(_)) = result {
+                    evictions += 1;
                 }
-            };
 
-            // This can fail, but we don't care.
-            let _ = resp.write(&mut writer);
+                ItemResult { url, result }
+            })
+            .collect();
+
+        let evicted = queued_before.into_iter().take(evictions).collect();
+        (results, evicted)
+    }
+
+    /// Adds the pull request at `url` to the queue unless it is queued already,
+    /// in which case it takes the given `options`.
+    /// A full queue rejects the pull request or evicts the oldest one, as configured
+    /// and if `evictable`.
+    fn push(
+        queue: &mut Vec<PullRequest>,
+        url: String,
+        options: &Options,
+        settings: &config::Queue,
+        evictable: bool,
+    ) -> Result<PushOutcome, FailureKind> {
+        let pull_request = PullRequest {
+            options: options.clone(),
+            queued_at: Some(unix_timestamp()),
+            ..PullRequest::new(url)
+                .map_err(|err| FailureKind::InvalidPullRequest(err.to_string()))?
+        };
+
+        if let Some(queued) = queue.iter_mut().find(|queued| queued.same(&pull_request)) {
+            if queued.options == pull_request.options {
+                return Ok(PushOutcome::Duplicate);
+            }
+
+            queued.options = pull_request.options;
+            return Ok(PushOutcome::Updated);
         }
+
+        if queue.len() >= settings.limit as usize {
+            if settings.when_full == WhenFull::Reject || queue.is_empty() || !evictable {
+                return Err(FailureKind::QueueFull(settings.limit));
+            }
+
+            let evicted = queue.remove(0);
+            log::info!("Queue is full, evicted {:?}", evicted.url);
+            queue.push(pull_request);
+
+            return Ok(PushOutcome::Evicted(evicted.url.unwrap_or_default()));
+        }
+
+        queue.push(pull_request);
+        Ok(PushOutcome::Added)
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    /// Removes the pull request at `url` from the queue and remembers it as dismissed,
+    /// so it isn't queued again from a watched repository.
+    fn pop(
+        queue: &mut Vec<PullRequest>,
+        dismissed: &mut Vec<PullRequest>,
+        url: String,
+    ) -> Result<PopOutcome, FailureKind> {
+        let pull_request = PullRequest::new(url)
+            .map_err(|err| FailureKind::InvalidPullRequest(err.to_string()))?;
+
+        match queue.iter().position(|queued| queued.same(&pull_request)) {
+            Some(position) => {
+                dismissed.push(queue.remove(position));
+                Ok(PopOutcome::Removed)
+            }
+            None => Ok(PopOutcome::NotFound),
+        }
+    }
+
+    /// Writes a single `Response` to an asynchronous client connection.
+    async fn respond<W: AsyncWrite + Unpin>(writer: &mut W, response: Response) -> Result<()> {
+        let mut buffer = Vec::new();
+        response.write(&mut buffer)?;
+        writer.write_all(&buffer).await?;
+        writer.flush().await?;
+
+        Ok(())
+    }
+
+    /// Accepts clients until one of them asks the agent to quit or a
+    /// SIGTERM/SIGINT arrives, then shuts the agent down gracefully.
+    /// Every client is served on its own task, so a slow client never blocks the others.
+    pub async fn run(&mut self) -> Result<()> {
         let listener = UnixListener::bind(&self.agent_path)?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut hangup = signal(SignalKind::hangup())?;
 
-        self.setup_process()?;
+        self.write_pid()?;
 
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    self.handle_client(stream);
-                    if self.quitting {
-                        break;
+        let interval_in_minutes = self
+            .shared
+            .configuration
+            .read()
+            .await
+            .queue
+            .interval_in_minutes;
+        Self::schedule(&self.shared, interval_in_minutes).await?;
+
+        let watcher = if self.watch_config {
+            Some(task::spawn(Self::watch(self.shared.clone())))
+        } else {
+            None
+        };
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    match accepted {
+                        Ok((stream, _)) => {
+                            task::spawn(Self::handle_client(self.shared.clone(), stream));
+                        }
+                        Err(_e) => {
+                            continue;
+                        }
                     }
                 }
-                Err(_e) => {
-                    continue;
+                _ = self.shared.quit.notified() => {
+                    log::info!("Quit requested, shutting down");
+                    break;
+                }
+                _ = terminate.recv() => {
+                    log::info!("Received SIGTERM, shutting down");
+                    break;
+                }
+                _ = interrupt.recv() => {
+                    log::info!("Received SIGINT, shutting down");
+                    break;
+                }
+                _ = hangup.recv() => {
+                    log::info!("Received SIGHUP, reloading configuration");
+                    // Replacing the scheduler waits for a queue run in progress,
+                    // which must not keep clients and signals from being handled.
+                    let shared = self.shared.clone();
+                    task::spawn(async move {
+                        if let Err(err) = Self::reload(&shared).await {
+                            log::error!("Failed to reload configuration: {}", err);
+                        }
+                    });
                 }
             }
         }
 
+        if let Some(watcher) = watcher {
+            watcher.abort();
+        }
+
+        drop(listener);
+        self.shutdown().await
+    }
+
+    /// Stops the scheduler, waits for in-flight queue runs and flushes the queue to disk.
+    /// The socket itself is removed once the agent is dropped.
+    async fn shutdown(&mut self) -> Result<()> {
+        let scheduler = self.shared.scheduler.lock().unwrap().take();
+        if let Some(scheduler) = scheduler {
+            // Stopping joins the scheduler thread, which may be blocked on a queue run.
+            task::spawn_blocking(move || scheduler.stop()).await?;
+        }
+
+        let _runs = self.shared.runs.write().await;
+        crate::state::store(&REPOS.lock().await)?;
+
+        log::info!("Agent shut down");
+
         Ok(())
     }
 }
 
 impl Drop for Agent {
     fn drop(&mut self) {
-        #[allow(clippy::expect_used)]
-        fs::remove_file(Agent::path()).expect("attempted to remove missing agent socket");
+        match fs::remove_file(&self.agent_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => log::error!("Failed to remove agent socket: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pull_request::MergeMethod;
+    use anyhow::Result;
+    use tokio::io::AsyncReadExt;
+
+    fn shared() -> Arc<Shared> {
+        Arc::new(Shared {
+            configuration: RwLock::new(Configuration::default()),
+            scheduler: Mutex::new(None),
+            started_at: Instant::now(),
+            run_state: Mutex::new(RunState::default()),
+            quit: Notify::new(),
+            runs: RwLock::new(()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_peer_uid_of_stream_pair() -> Result<()> {
+        let (left, right) = UnixStream::pair()?;
+
+        assert_eq!(Agent::peer_uid(&left), Some(Uid::effective()));
+        assert_eq!(Agent::peer_uid(&right), Some(Uid::effective()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auth_client_accepts_same_user() -> Result<()> {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "{}-auth-test-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path)?;
+        let _client = UnixStream::connect(&path).await?;
+        let (server, _) = listener.accept().await?;
+
+        assert_eq!(Agent::auth_client(&server), true);
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorized_rejects_other_user() {
+        let other = Uid::from_raw(Uid::effective().as_raw().wrapping_add(1));
+
+        assert_eq!(Agent::authorized(Some(other)), false);
+        assert_eq!(Agent::authorized(None), false);
+        assert_eq!(Agent::authorized(Some(Uid::effective())), true);
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "{}-{}-test-{}",
+            env!("CARGO_PKG_NAME"),
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() -> Result<()> {
+        let path = test_path("lock");
+
+        let lock = Agent::lock(&path)?;
+        assert!(Agent::lock(&path).is_err());
+
+        drop(lock);
+        assert!(Agent::lock(&path).is_ok());
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alive_detects_stale_socket() -> Result<()> {
+        let path = test_path("stale");
+
+        let listener = std::os::unix::net::UnixListener::bind(&path)?;
+        assert_eq!(Agent::alive(&path), true);
+
+        drop(listener);
+        assert_eq!(path.exists(), true);
+        assert_eq!(Agent::alive(&path), false);
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idle_client_does_not_block_others() -> Result<()> {
+        let shared = shared();
+
+        let (_idle, idle_server) = UnixStream::pair()?;
+        task::spawn(Agent::handle_client(shared.clone(), idle_server));
+
+        let (mut client, server) = UnixStream::pair()?;
+        task::spawn(Agent::handle_client(shared.clone(), server));
+
+        let mut request = Vec::new();
+        Request {
+            protocol: PROTOCOL_VERSION,
+            body: RequestBody::List,
+        }
+        .write(&mut request)?;
+        client.write_all(&request).await?;
+
+        let mut line = String::new();
+        BufReader::new(&mut client).read_line(&mut line).await?;
+        let response: Response = serde_json::from_str(&line)?;
+
+        assert!(matches!(response, Response::List(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_other_protocol_version_is_reported() -> Result<()> {
+        let (mut client, server) = UnixStream::pair()?;
+        task::spawn(Agent::handle_client(shared(), server));
+
+        // A request of another version may not parse at all.
+        let request = format!(
+            "{{\"protocol\":{},\"body\":{{\"type\":\"Unknown\"}}}}\n",
+            PROTOCOL_VERSION + 1
+        );
+        client.write_all(request.as_bytes()).await?;
+
+        let mut line = String::new();
+        BufReader::new(&mut client).read_line(&mut line).await?;
+        let response: Response = serde_json::from_str(&line)?;
+
+        assert_eq!(
+            response,
+            Response::Failure(FailureKind::VersionMismatch(PROTOCOL_VERSION))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_reports_outcome() {
+        let mut queue = Vec::new();
+        let url = "https://github.com/Liberatys/rusty-merge/pull/400".to_string();
+
+        let settings = config::Queue::default();
+        let options = Options {
+            no_update: true,
+            ..Options::default()
+        };
+
+        assert_eq!(
+            Agent::push(&mut queue, url.clone(), &options, &settings, true),
+            Ok(PushOutcome::Added)
+        );
+        assert_eq!(
+            Agent::push(&mut queue, url, &options, &settings, true),
+            Ok(PushOutcome::Duplicate)
+        );
+        assert_eq!(
+            Agent::push(
+                &mut queue,
+                "liberatys/Rusty-Merge/pull/400".into(),
+                &options,
+                &settings,
+                true
+            ),
+            Ok(PushOutcome::Duplicate)
+        );
+        assert!(matches!(
+            Agent::push(
+                &mut queue,
+                "Liberatys/rusty-merge/400".into(),
+                &options,
+                &settings,
+                true
+            ),
+            Err(FailureKind::InvalidPullRequest(_))
+        ));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].options, options);
+
+        let rebase = Options {
+            method: Some(MergeMethod::Rebase),
+            ..Options::default()
+        };
+        assert_eq!(
+            Agent::push(
+                &mut queue,
+                "Liberatys/rusty-merge#400".into(),
+                &rebase,
+                &settings,
+                true
+            ),
+            Ok(PushOutcome::Updated)
+        );
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].options, rebase);
+    }
+
+    #[test]
+    fn test_push_enforces_limit() {
+        let first = "https://github.com/Liberatys/rusty-merge/pull/1".to_string();
+        let second = "https://github.com/Liberatys/rusty-merge/pull/2".to_string();
+        let mut settings = config::Queue {
+            limit: 1,
+            ..config::Queue::default()
+        };
+        let options = Options::default();
+
+        let mut queue = Vec::new();
+        assert_eq!(
+            Agent::push(&mut queue, first.clone(), &options, &settings, true),
+            Ok(PushOutcome::Added)
+        );
+        assert_eq!(
+            Agent::push(&mut queue, second.clone(), &options, &settings, true),
+            Err(FailureKind::QueueFull(1))
+        );
+        assert_eq!(queue.len(), 1);
+
+        settings.when_full = WhenFull::EvictOldest;
+        assert_eq!(
+            Agent::push(&mut queue, second.clone(), &options, &settings, true),
+            Ok(PushOutcome::Evicted(first))
+        );
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].url, Some(second));
+    }
+
+    #[test]
+    fn test_push_all_keeps_pushed_pull_requests() {
+        let url = |number: u64| format!("https://github.com/Liberatys/rusty-merge/pull/{}", number);
+        let settings = config::Queue {
+            limit: 2,
+            when_full: WhenFull::EvictOldest,
+            ..config::Queue::default()
+        };
+        let options = Options::default();
+
+        let mut queue = vec![PullRequest {
+            source: Source::Label,
+            ..PullRequest::numbered(1)
+        }];
+        let (results, evicted) = Agent::push_all(
+            &mut queue,
+            vec![url(2), url(3), url(4)],
+            &options,
+            &settings,
+        );
+
+        assert_eq!(
+            results
+                .into_iter()
+                .map(|item| item.result)
+                .collect::<Vec<_>>(),
+            vec![
+                Ok(PushOutcome::Added),
+                Ok(PushOutcome::Evicted(url(1))),
+                Err(FailureKind::QueueFull(2))
+            ]
+        );
+        assert_eq!(
+            queue.iter().map(|p| p.request).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        // Handed back to remove the `merging` label from it.
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].source, Source::Label);
+    }
+
+    #[test]
+    fn test_trim_to_lowered_limit() {
+        let mut queue: Vec<PullRequest> = (1..=4).map(PullRequest::numbered).collect();
+        let mut settings = config::Queue {
+            limit: 2,
+            ..config::Queue::default()
+        };
+
+        assert!(Agent::trim(&mut queue, &settings).is_empty());
+
+        settings.when_full = WhenFull::EvictOldest;
+        let evicted = Agent::trim(&mut queue, &settings);
+        assert_eq!(
+            evicted.iter().map(|p| p.request).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            queue.iter().map(|p| p.request).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+    }
+
+    #[test]
+    fn test_pop_reports_outcome() {
+        let url = "https://github.com/Liberatys/rusty-merge/pull/400".to_string();
+        let mut queue = vec![PullRequest::new(url.clone()).unwrap()];
+        let mut dismissed = Vec::new();
+
+        assert_eq!(
+            Agent::pop(
+                &mut queue,
+                &mut dismissed,
+                "liberatys/Rusty-Merge//pull/400".into()
+            ),
+            Ok(PopOutcome::Removed)
+        );
+        assert_eq!(
+            Agent::pop(&mut queue, &mut dismissed, url.clone()),
+            Ok(PopOutcome::NotFound)
+        );
+        assert!(matches!(
+            Agent::pop(
+                &mut queue,
+                &mut dismissed,
+                "Liberatys/rusty-merge/400".into()
+            ),
+            Err(FailureKind::InvalidPullRequest(_))
+        ));
+        assert_eq!(dismissed, vec![PullRequest::new(url).unwrap()]);
+    }
+
+    #[test]
+    fn test_enqueue_discovered_skips_dismissed() {
+        let settings = config::Queue {
+            limit: 3,
+            ..config::Queue::default()
+        };
+
+        let mut queue = vec![PullRequest::numbered(1)];
+        let mut dismissed = vec![PullRequest::numbered(2), PullRequest::numbered(9)];
+        let found: Vec<PullRequest> = (1..=5).map(PullRequest::numbered).collect();
+
+        Agent::forget_dismissed(&mut dismissed, &found);
+        assert_eq!(
+            Agent::enqueue_discovered(&mut queue, &dismissed, found, &settings),
+            vec![PullRequest::numbered(3), PullRequest::numbered(4)]
+        );
+        assert_eq!(
+            queue,
+            vec![
+                PullRequest::numbered(1),
+                PullRequest::numbered(3),
+                PullRequest::numbered(4)
+            ]
+        );
+        // #9 is no longer found and therefore forgotten.
+        assert_eq!(dismissed, vec![PullRequest::numbered(2)]);
+    }
+
+    #[test]
+    fn test_drop_unlabeled_keeps_pushed() {
+        let pull_request = |number: u64, source: Source| PullRequest {
+            source,
+            ..PullRequest::numbered(number)
+        };
+
+        let mut queue = vec![
+            pull_request(1, Source::Label),
+            pull_request(2, Source::Label),
+            pull_request(3, Source::Push),
+        ];
+        let labeled = vec![pull_request(1, Source::Label)];
+
+        assert_eq!(
+            Agent::drop_unlabeled(&mut queue, &labeled),
+            vec![pull_request(2, Source::Label)]
+        );
+        assert_eq!(
+            queue,
+            vec![
+                pull_request(1, Source::Label),
+                pull_request(3, Source::Push)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_runs() {
+        let shared = shared();
+
+        let status = Agent::status(&shared).await;
+        assert_eq!(status.protocol, PROTOCOL_VERSION);
+        assert_eq!(status.pid, std::process::id());
+        assert_eq!(status.last_run, None);
+        assert_eq!(status.running, false);
+
+        shared.run_state.lock().unwrap().try_start();
+        assert_eq!(Agent::status(&shared).await.running, true);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_run_resets_run_state() {
+        let shared = shared();
+        shared.run_state.lock().unwrap().last_run = Some(Run {
+            started_at: unix_timestamp(),
+            finished_at: None,
+            result: None,
+        });
+        assert_eq!(
+            shared.run_state.lock().unwrap().force(),
+            ForceOutcome::Started
+        );
+
+        Agent::run_repeatedly(shared.clone(), |_| async { panic!("boom") }).await;
+
+        let mut run_state = shared.run_state.lock().unwrap();
+        assert_eq!(run_state.running, false);
+        assert!(matches!(
+            run_state
+                .last_run
+                .as_ref()
+                .and_then(|run| run.result.as_ref()),
+            Some(Err(_))
+        ));
+        assert_eq!(run_state.try_start(), true);
+    }
+
+    #[test]
+    fn test_run_state_allows_single_run() {
+        let mut state = RunState::default();
+
+        assert_eq!(state.try_start(), true);
+        assert_eq!(state.try_start(), false);
+        assert_eq!(state.finish(), false);
+        assert_eq!(state.try_start(), true);
+    }
+
+    #[test]
+    fn test_run_state_queues_one_follow_up() {
+        let mut state = RunState::default();
+
+        assert_eq!(state.force(), ForceOutcome::Started);
+        assert_eq!(state.force(), ForceOutcome::FollowUpQueued);
+        assert_eq!(state.force(), ForceOutcome::MergedIntoFollowUp);
+
+        // The follow-up run takes over, further forces queue a new one.
+        assert_eq!(state.finish(), true);
+        assert_eq!(state.running, true);
+        assert_eq!(state.force(), ForceOutcome::FollowUpQueued);
+
+        assert_eq!(state.finish(), true);
+        assert_eq!(state.finish(), false);
+        assert_eq!(state.running, false);
+    }
+
+    #[tokio::test]
+    async fn test_quit_notifies_agent() -> Result<()> {
+        let shared = shared();
+        let (mut client, server) = UnixStream::pair()?;
+        let handler = task::spawn(Agent::handle_client(shared.clone(), server));
+
+        let mut request = Vec::new();
+        Request {
+            protocol: PROTOCOL_VERSION,
+            body: RequestBody::Quit,
+        }
+        .write(&mut request)?;
+        client.write_all(&request).await?;
+
+        shared.quit.notified().await;
+        handler.await?;
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await?;
+        assert_eq!(response, "{\"type\":\"Success\",\"body\":\"OK\"}\n");
+
+        Ok(())
     }
 }
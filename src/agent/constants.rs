@@ -1 +1,3 @@
-pub const PROTOCOL_VERSION: u32 = 1;
+/// The version of the format of requests and responses,
+/// bumped with every change to it so mismatched clients and agents are told so.
+pub const PROTOCOL_VERSION: u32 = 11;
@@ -1,3 +1,4 @@
+use crate::pull_request::{Options, State};
 use anyhow::Result;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -9,15 +10,28 @@ pub struct Request {
     pub body: RequestBody,
 }
 
+/// The part of a `Request` every protocol version agrees on. Read before the request itself,
+/// so a client of another version is told so even if its request can't be parsed.
+#[derive(Debug, Deserialize)]
+pub struct Envelope {
+    pub protocol: u32,
+}
+
 /// Represents the kinds of requests understood by the agent.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(tag = "type", content = "body")]
 pub enum RequestBody {
-    Push(String),
-    Pop(String),
+    /// Queues pull requests, all of them with the same `Options`.
+    Push {
+        urls: Vec<String>,
+        options: Options,
+    },
+    Pop(Vec<String>),
     ForceProcess,
     Clear,
     List,
+    Reload,
+    Status,
     Quit,
 }
 
@@ -28,14 +42,113 @@ pub enum Response {
     /// A successful request, with some request-specific response data.
     Success(String),
 
-    List(Vec<String>),
+    /// The queued pull requests, answered to `RequestBody::List`.
+    List(Vec<Entry>),
+
+    Status(Status),
+
+    /// The outcome of a `RequestBody::Push`, for each of its pull requests.
+    Pushed(Vec<ItemResult<PushOutcome>>),
+
+    /// The outcome of a `RequestBody::Pop`, for each of its pull requests.
+    Popped(Vec<ItemResult<PopOutcome>>),
+
+    /// The outcome of a `RequestBody::ForceProcess`.
+    Forced(ForceOutcome),
 
     /// A failed request, of `FailureKind`.
     Failure(FailureKind),
 }
 
-/// Represents the kinds of failures encoded by a `kbs2` `Response`.
+/// The result for a single pull request of a bulk request.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct ItemResult<T> {
+    /// The pull request as given in the request.
+    pub url: String,
+    pub result: Result<T, FailureKind>,
+}
+
+/// What happened to a pull request pushed into the queue.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub enum PushOutcome {
+    /// The pull request was added to the queue.
+    Added,
+
+    /// The pull request was added to a full queue, evicting the oldest pull request.
+    Evicted(String),
+
+    /// The pull request was queued already.
+    Duplicate,
+
+    /// The pull request was queued already with other options, which it now has instead.
+    Updated,
+}
+
+/// What happened to a pull request popped from the queue.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Serialize)]
+pub enum PopOutcome {
+    /// The pull request was removed from the queue.
+    Removed,
+
+    /// The pull request wasn't queued.
+    NotFound,
+}
+
+/// What happened to a forced run of the queue process.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Serialize)]
+pub enum ForceOutcome {
+    /// A new run was started.
+    Started,
+
+    /// A run was in progress, another run follows once it finishes.
+    FollowUpQueued,
+
+    /// A run was in progress and a follow-up run was queued already.
+    MergedIntoFollowUp,
+}
+
+/// A queued pull request and where it stands.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    pub url: String,
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+    pub state: State,
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch, `None` for pull requests queued by older agents.
+    pub queued_at: Option<u64>,
+    /// Seconds since the Unix epoch, `None` until the runner checked the pull request.
+    pub last_checked: Option<u64>,
+    /// How many runs in a row failed on the pull request.
+    pub failures: u32,
+}
+
+/// Describes what a running agent is doing, answered to `RequestBody::Status`.
+/// All points in time are seconds since the Unix epoch.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Status {
+    pub protocol: u32,
+    pub pid: u32,
+    pub uptime_in_seconds: u64,
+    pub config_path: String,
+    pub queue_length: usize,
+    pub last_run: Option<Run>,
+    pub running: bool,
+    pub next_run: Option<u64>,
+}
+
+/// A single run of the queue process.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct Run {
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    /// `None` while running, otherwise `Ok(())` or the error that ended the run.
+    pub result: Option<Result<(), String>>,
+}
+
+/// Represents the kinds of failures encoded by a `kbs2` `Response`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 #[serde(tag = "type", content = "body")]
 pub enum FailureKind {
     /// The request failed because one or more I/O operations failed.
@@ -50,6 +163,15 @@ pub enum FailureKind {
     /// The request failed because the agent and client don't speak the same protocol version.
     VersionMismatch(u32),
 
+    /// The request failed because the pull request reference is invalid.
+    InvalidPullRequest(String),
+
+    /// The request failed because the queue holds its configured limit of pull requests.
+    QueueFull(u32),
+
+    /// The request failed because the configuration could not be loaded or is invalid.
+    Config(String),
+
     Auth,
 }
 
@@ -1,5 +1,10 @@
-use crate::agent::{Agent, Message, Request, RequestBody, Response, PROTOCOL_VERSION};
-use anyhow::Result;
+use crate::agent::{
+    Agent, Entry, FailureKind, ForceOutcome, ItemResult, Message, PopOutcome, PushOutcome, Request,
+    RequestBody, Response, Status, PROTOCOL_VERSION,
+};
+use crate::output::describe;
+use crate::pull_request::Options;
+use anyhow::{bail, Result};
 use std::os::unix::net::UnixStream;
 
 pub struct Client {
@@ -30,40 +35,73 @@ impl Client {
         Ok(())
     }
 
-    /// Ask the agent to quit gracefully.
-    pub fn push(self, url: String) -> Result<()> {
-        self.request(RequestBody::Push(url))?;
-        Ok(())
+    /// Ask the agent to reload its configuration file.
+    pub fn reload(self) -> Result<()> {
+        match self.request(RequestBody::Reload)? {
+            Response::Failure(FailureKind::Config(err)) => {
+                bail!("failed to reload configuration: {}", err)
+            }
+            Response::Failure(failure) => bail!("failed to reload configuration: {:?}", failure),
+            _ => Ok(()),
+        }
     }
 
-    /// Ask the agent to quit gracefully.
-    pub fn pop(self, url: String) -> Result<()> {
-        self.request(RequestBody::Pop(url))?;
-        Ok(())
+    /// Ask the agent what it is doing.
+    pub fn status(self) -> Result<Status> {
+        match self.request(RequestBody::Status)? {
+            Response::Status(status) => Ok(status),
+            Response::Failure(failure) => bail!("failed to get agent status: {:?}", failure),
+            response => bail!("unexpected response: {:?}", response),
+        }
     }
 
-    /// Ask the agent to quit gracefully.
+    /// Ask the agent to add pull requests to the queue.
+    pub fn push(self, urls: Vec<String>, options: Options) -> Result<Vec<ItemResult<PushOutcome>>> {
+        match self.request(RequestBody::Push { urls, options })? {
+            Response::Pushed(results) => Ok(results),
+            response => Self::failure(response).map(|_| Vec::new()),
+        }
+    }
+
+    /// Ask the agent to remove pull requests from the queue.
+    pub fn pop(self, urls: Vec<String>) -> Result<Vec<ItemResult<PopOutcome>>> {
+        match self.request(RequestBody::Pop(urls))? {
+            Response::Popped(results) => Ok(results),
+            response => Self::failure(response).map(|_| Vec::new()),
+        }
+    }
+
+    /// Ask the agent to clear the queue.
     pub fn clear(self) -> Result<()> {
-        self.request(RequestBody::Clear)?;
-        Ok(())
+        match self.request(RequestBody::Clear)? {
+            Response::Success(_) => Ok(()),
+            response => Self::failure(response),
+        }
     }
 
-    /// Ask the agent to quit gracefully.
-    pub fn force(self) -> Result<()> {
+    /// Turns a failed or unexpected `Response` into an error.
+    fn failure(response: Response) -> Result<()> {
+        match response {
+            Response::Failure(failure) => bail!("{}", describe(&failure)),
+            response => bail!("unexpected response: {:?}", response),
+        }
+    }
+
+    /// Ask the agent to run the queue process now.
+    pub fn force(self) -> Result<ForceOutcome> {
         log::info!("Client-Command: Force");
-        self.request(RequestBody::ForceProcess)?;
-        Ok(())
+        match self.request(RequestBody::ForceProcess)? {
+            Response::Forced(outcome) => Ok(outcome),
+            Response::Failure(failure) => bail!("failed to force a queue run: {:?}", failure),
+            response => bail!("unexpected response: {:?}", response),
+        }
     }
 
-    /// Ask the agent to quit gracefully.
-    pub fn list(self) -> Result<()> {
-        let response = self.request(RequestBody::List)?;
-        match response {
-            Response::List(list) => {
-                println!("Queue: \n{}", list.join("\n"));
-            }
-            _ => {}
+    /// Ask the agent for the queued pull requests and where they stand.
+    pub fn list(self) -> Result<Vec<Entry>> {
+        match self.request(RequestBody::List)? {
+            Response::List(entries) => Ok(entries),
+            response => Self::failure(response).map(|_| Vec::new()),
         }
-        Ok(())
     }
 }
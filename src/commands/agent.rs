@@ -1,28 +1,38 @@
 use crate::agent::Agent;
 use crate::client::Client;
 use crate::config::Configuration;
+use crate::output::{self, Output};
 use anyhow::Result;
 use clap::ArgMatches;
 use daemonize_me::Daemon;
+use tokio::runtime::Runtime;
 
 pub fn agent(matches: &ArgMatches) -> Result<()> {
-    let config: Configuration = crate::config::load()?;
-    crate::config::store(&config)?;
-
     // No subcommand: run the agent itself
     if matches.subcommand().is_none() {
+        // The subcommands only talk to the running agent, which validates on reload,
+        // so they keep working while the configuration file is invalid.
+        let config: Configuration = crate::config::load()?;
+        crate::config::validate(&config)?;
+        crate::config::store(&config)?;
+
         let mut agent = Agent::new(config)?;
+        agent.watch_config(matches.is_present("watch"));
         if !matches.is_present("foreground") {
             Daemon::new().start()?;
         }
         log::info!("Starting agent");
-        agent.run()?;
+        // The runtime is only built after daemonizing, forking a running
+        // runtime would leave the daemon without its worker threads.
+        Runtime::new()?.block_on(agent.run())?;
 
         return Ok(());
     }
 
     match matches.subcommand() {
         Some(("kill", matches)) => agent_kill(matches),
+        Some(("reload", matches)) => agent_reload(matches),
+        Some(("status", matches)) => agent_status(matches),
         _ => unreachable!(),
     }
 }
@@ -35,3 +45,17 @@ fn agent_kill(_matches: &ArgMatches) -> Result<()> {
 
     Ok(())
 }
+
+fn agent_reload(_matches: &ArgMatches) -> Result<()> {
+    log::info!("Reloading agent configuration");
+
+    let client = Client::new()?;
+    client.reload()?;
+
+    Ok(())
+}
+
+fn agent_status(matches: &ArgMatches) -> Result<()> {
+    let client = Client::new()?;
+    output::status(Output::from_matches(matches), &client.status()?)
+}
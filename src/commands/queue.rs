@@ -1,36 +1,71 @@
+use crate::agent::{FailureKind, ItemResult, PopOutcome};
 use crate::client::Client;
-use crate::pull_request::PullRequest;
-use anyhow::Result;
+use crate::github;
+use crate::output::{self, Output};
+use crate::pull_request::{Options, PullRequest};
+use anyhow::{bail, Context, Result};
 use clap::ArgMatches;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use tokio::runtime::Runtime;
 
-pub fn list(_matches: &ArgMatches) -> Result<()> {
+pub fn list(matches: &ArgMatches) -> Result<()> {
     log::info!("Command: List");
 
     let client = Client::new()?;
-    client.list()?;
-
-    Ok(())
+    output::list(Output::from_matches(matches), &client.list()?)
 }
 
-pub fn clear(_matches: &ArgMatches) -> Result<()> {
+pub fn clear(matches: &ArgMatches) -> Result<()> {
     log::info!("Command: Clear");
 
     let client = Client::new()?;
     client.clear()?;
 
-    Ok(())
+    output::cleared(Output::from_matches(matches))
 }
 
 pub fn push(matches: &ArgMatches) -> Result<()> {
     log::info!("Command: Push");
 
-    if let Some(url) = matches.value_of("URL") {
-        if !PullRequest::valid(url) {
-            panic!("{} is an invalid pull request source", url);
+    let output = Output::from_matches(matches);
+    let query = matches.value_of("query");
+    let from_stdin = matches
+        .values_of("URL")
+        .map_or(false, |mut values| values.any(|value| value == "-"));
+    if from_stdin && query.is_some() && !matches.is_present("yes") {
+        bail!("pushing the matches of --query can't be confirmed while reading stdin, pass --yes");
+    }
+
+    let mut references = references(matches)?;
+    if let Some(query) = query {
+        references.extend(search(query, matches.is_present("yes"), output)?);
+    }
+
+    if references.is_empty() {
+        // Nothing matched the query or pushing its matches was declined.
+        if query.is_some() {
+            return output::pushed(output, &[]);
         }
 
+        bail!("no pull requests given");
+    }
+
+    let (urls, mut results) = resolve_all(&references);
+    if !urls.is_empty() {
         let client = Client::new()?;
-        client.push(url.to_string())?;
+        results.extend(client.push(urls, options(matches)?)?);
+    }
+    output::pushed(output, &results)?;
+
+    let failed = results.iter().filter(|item| item.result.is_err()).count();
+    if failed > 0 {
+        bail!(
+            "{} of {} pull requests could not be pushed",
+            failed,
+            references.len()
+        );
     }
 
     Ok(())
@@ -39,19 +74,199 @@ pub fn push(matches: &ArgMatches) -> Result<()> {
 pub fn force(matches: &ArgMatches) -> Result<()> {
     let client = Client::new()?;
     log::info!("Command: Force");
-    client.force()?;
+    let outcome = client.force()?;
 
-    Ok(())
+    output::forced(Output::from_matches(matches), outcome)
 }
 
 pub fn pop(matches: &ArgMatches) -> Result<()> {
-    if let Some(url) = matches.value_of("URL") {
-        if !PullRequest::valid(url) {
-            panic!("{} is an invalid pull request source", url);
-        }
+    log::info!("Command: Pop");
+
+    let references = references(matches)?;
+    if references.is_empty() {
+        bail!("no pull requests given");
+    }
+
+    let (urls, mut results) = resolve_all(&references);
+    if !urls.is_empty() {
         let client = Client::new()?;
-        client.pop(url.to_string())?;
+        results.extend(client.pop(urls)?);
+    }
+    output::popped(Output::from_matches(matches), &results)?;
+
+    let failed = results
+        .iter()
+        .filter(|item| !matches!(item.result, Ok(PopOutcome::Removed)))
+        .count();
+    if failed > 0 {
+        bail!(
+            "{} of {} pull requests could not be popped",
+            failed,
+            references.len()
+        );
     }
 
     Ok(())
 }
+
+/// The merge options given to `push`.
+fn options(matches: &ArgMatches) -> Result<Options> {
+    for template in ["title", "message"]
+        .iter()
+        .filter_map(|arg| matches.value_of(arg))
+    {
+        crate::template::validate(template)?;
+    }
+
+    Ok(Options {
+        method: matches.value_of("method").map(str::parse).transpose()?,
+        title: matches.value_of("title").map(String::from),
+        message: matches.value_of("message").map(String::from),
+        no_update: matches.is_present("no-update"),
+        delete_branch: matches.is_present("delete-branch"),
+    })
+}
+
+/// Collects the pull request references given as arguments, where `-` reads them from stdin,
+/// and from the file passed to `--from-file`.
+fn references(matches: &ArgMatches) -> Result<Vec<String>> {
+    let mut references = Vec::new();
+
+    if let Some(values) = matches.values_of("URL") {
+        for value in values {
+            if value == "-" {
+                references.extend(read_references(io::stdin().lock())?);
+            } else {
+                references.push(value.to_string());
+            }
+        }
+    }
+
+    if let Some(path) = matches.value_of("from-file") {
+        let file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+        references.extend(read_references(BufReader::new(file))?);
+    }
+
+    Ok(references)
+}
+
+/// Searches GitHub for pull requests matching `query`,
+/// returning their urls once the user confirmed to push them.
+/// The matches are listed on stderr with JSON output, leaving stdout to the results.
+fn search(query: &str, confirmed: bool, output: Output) -> Result<Vec<String>> {
+    let print = |line: String| {
+        if output == Output::Json {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+
+    let results = Runtime::new()?.block_on(async {
+        let octocrab = github::client()?;
+        github::search_pull_requests(&octocrab, query).await
+    })?;
+
+    if results.is_empty() {
+        print(format!("No pull requests match {}", query));
+        return Ok(Vec::new());
+    }
+
+    print(format!("Found {} pull requests:", results.len()));
+    for result in &results {
+        let pull_request = &result.pull_request;
+        print(format!(
+            "  {}/{}#{} {}",
+            pull_request.owner, pull_request.repo, pull_request.request, result.title
+        ));
+    }
+
+    if !confirmed && !confirm(&format!("Push {} pull requests?", results.len()))? {
+        return Ok(Vec::new());
+    }
+
+    Ok(results
+        .into_iter()
+        .filter_map(|result| result.pull_request.url)
+        .collect())
+}
+
+/// Asks a yes or no question on stderr, keeping stdout to the results.
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Reads one pull request reference per line, skipping blank lines.
+fn read_references<R: BufRead>(reader: R) -> Result<Vec<String>> {
+    let mut references = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if !line.is_empty() {
+            references.push(line.to_string());
+        }
+    }
+
+    Ok(references)
+}
+
+/// Resolves all references.
+/// Returns the resolved urls and a failed result for each reference that can't be resolved.
+fn resolve_all<T>(references: &[String]) -> (Vec<String>, Vec<ItemResult<T>>) {
+    let mut urls = Vec::new();
+    let mut failed = Vec::new();
+
+    for reference in references {
+        match resolve(reference) {
+            Ok(url) => urls.push(url),
+            Err(err) => failed.push(ItemResult {
+                url: reference.clone(),
+                result: Err(FailureKind::InvalidPullRequest(err.to_string())),
+            }),
+        }
+    }
+
+    (urls, failed)
+}
+
+/// Resolves a pull request reference to its url on the client side,
+/// as short references depend on the git checkout in the current directory.
+fn resolve(reference: &str) -> Result<String> {
+    if !PullRequest::valid(reference) {
+        bail!("{} is an invalid pull request source", reference);
+    }
+
+    let pull_request = PullRequest::resolve(reference)?;
+
+    Ok(pull_request.url.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_references() -> Result<()> {
+        let input =
+            "Liberatys/rusty-merge#1\n\n  #2  \nhttps://github.com/Liberatys/rusty-merge/pull/3\n";
+
+        assert_eq!(
+            read_references(input.as_bytes())?,
+            vec![
+                "Liberatys/rusty-merge#1",
+                "#2",
+                "https://github.com/Liberatys/rusty-merge/pull/3"
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use clap::{arg, command};
-use clap::{Arg, ArgMatches, Command, ValueHint};
+use clap::{Arg, ArgGroup, ArgMatches, Command, ValueHint};
 
 pub fn app() -> Command<'static> {
     Command::new(env!("CARGO_PKG_NAME"))
@@ -7,6 +7,15 @@ pub fn app() -> Command<'static> {
         .arg_required_else_help(true)
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::new("output")
+                .help("how results and errors are printed")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["text", "json", "table"])
+                .default_value("text"),
+        )
         .subcommand(
             Command::new("agent")
                 .about("run the agent")
@@ -16,19 +25,96 @@ pub fn app() -> Command<'static> {
                         .short('F')
                         .long("foreground"),
                 )
-                .subcommand(Command::new("kill").about("kills the background agent")),
+                .arg(
+                    Arg::new("watch")
+                        .help("reload the configuration when its file changes")
+                        .short('w')
+                        .long("watch"),
+                )
+                .subcommand(Command::new("kill").about("kills the background agent"))
+                .subcommand(Command::new("reload").about("reloads the configuration of the agent"))
+                .subcommand(Command::new("status").about("shows what the agent is doing")),
         )
         .subcommand(Command::new("clear").about("clear the current queue"))
         .subcommand(Command::new("force").about("force the current queue process"))
         .subcommand(Command::new("list").about("list the current queue"))
         .subcommand(
             Command::new("push")
-                .about("push a pull request into the queue")
-                .arg(arg!(<URL>)),
+                .about("push pull requests into the queue")
+                .arg(references_arg())
+                .arg(from_file_arg())
+                .arg(
+                    Arg::new("query")
+                        .help("push the pull requests matching a GitHub search query")
+                        .long("query")
+                        .takes_value(true)
+                        .value_name("QUERY"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .help("push the matches of --query without asking")
+                        .short('y')
+                        .long("yes")
+                        .requires("query"),
+                )
+                .arg(
+                    Arg::new("method")
+                        .help("merge method, instead of the configured one")
+                        .long("method")
+                        .takes_value(true)
+                        .possible_values(["merge", "squash", "rebase"]),
+                )
+                .arg(
+                    Arg::new("title")
+                        .help("title of the merge commit")
+                        .long("title")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("message of the merge commit")
+                        .long("message")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("no-update")
+                        .help("never update the branch with its base")
+                        .long("no-update"),
+                )
+                .arg(
+                    Arg::new("delete-branch")
+                        .help("delete the branch once merged")
+                        .long("delete-branch"),
+                )
+                .group(sources_group(&["URL", "from-file", "query"])),
         )
         .subcommand(
             Command::new("pop")
-                .about("pop a pull request from the queue")
-                .arg(arg!(<URL>)),
+                .about("pop pull requests from the queue")
+                .arg(references_arg())
+                .arg(from_file_arg())
+                .group(sources_group(&["URL", "from-file"])),
         )
 }
+
+fn references_arg() -> Arg<'static> {
+    arg!([URL] "url, owner/repo#number or #number in the current repository, - reads from stdin")
+        .multiple_values(true)
+}
+
+fn from_file_arg() -> Arg<'static> {
+    Arg::new("from-file")
+        .help("read pull requests from a file, one per line")
+        .long("from-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .value_hint(ValueHint::FilePath)
+}
+
+/// Requires at least one of the given sources of pull requests.
+fn sources_group(sources: &[&'static str]) -> ArgGroup<'static> {
+    ArgGroup::new("sources")
+        .args(sources)
+        .required(true)
+        .multiple(true)
+}
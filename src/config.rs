@@ -1,4 +1,5 @@
-use anyhow::Result;
+use crate::pull_request::MergeMethod;
+use anyhow::{bail, Result};
 use directories_next::UserDirs;
 use serde::{Deserialize, Serialize};
 use std::default::Default;
@@ -13,18 +14,90 @@ pub struct Configuration {
     pub queue: Queue,
     pub merger: Merger,
     pub notifier: Notifier,
+    /// Queues pull requests labeled on GitHub.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Labels>,
+    /// Repositories polled for pull requests to queue automatically.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watch: Vec<Watch>,
+    /// Settings overridden for some repositories, see `Configuration::repository`.
+    #[serde(default, rename = "repository", skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<RepositorySettings>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Queue {
     pub limit: u32,
     pub interval_in_minutes: u32,
+    /// What happens to a push once `limit` pull requests are queued.
+    #[serde(default)]
+    pub when_full: WhenFull,
+    /// How many runs in a row may fail on a pull request before it is dropped from the queue.
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenFull {
+    /// Refuse to queue further pull requests.
+    Reject,
+    /// Drop the oldest queued pull request to make room.
+    EvictOldest,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Merger {
+    /// Template of the merge commit title, see `template::render` for its placeholders.
     pub title: Option<String>,
+    /// Template of the merge commit message.
     pub message: Option<String>,
+    #[serde(default)]
+    pub method: MergeMethod,
+    /// Without a `title`, use the title of the pull request followed by `(#number)`,
+    /// like GitHub does when merging from its UI.
+    #[serde(default)]
+    pub title_with_number: bool,
+}
+
+/// Settings for the repositories whose `owner/repo` matches the glob `name`, e.g. `my-org/*`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RepositorySettings {
+    pub name: String,
+    pub method: Option<MergeMethod>,
+    /// Only update and merge the oldest queued pull request of the repository,
+    /// the others wait until it is merged or popped.
+    #[serde(default)]
+    pub serial: bool,
+    /// The check runs and commit statuses that must pass before merging,
+    /// all reported ones if empty.
+    #[serde(default)]
+    pub required_checks: Vec<String>,
+}
+
+/// Queues the open pull requests of `authors` in `repositories` on every run.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Watch {
+    /// Repositories as `owner/repo`, or all repositories of an owner as `owner`.
+    pub repositories: Vec<String>,
+    /// Logins of the authors, where `dependabot`, `depfu` and `renovate` refer to their apps.
+    pub authors: Vec<String>,
+    /// Labels a pull request needs to carry to be queued.
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// Queues the open pull requests carrying `label` in `repositories` on every run,
+/// and drops them from the queue once the label is removed.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Labels {
+    /// Repositories as `owner/repo`, or all repositories of an owner as `owner`.
+    pub repositories: Vec<String>,
+    /// The label queueing a pull request, e.g. `automerge`.
+    pub label: String,
+    /// A label applied to pull requests queued through `label`,
+    /// removed again once they are popped or merged.
+    pub merging: Option<String>,
 }
 
 // TODO: Implement configuration for source
@@ -37,6 +110,12 @@ pub struct Notifier {
     pub pop: Option<Notification>,
     pub merge: Option<Notification>,
     pub update: Option<Notification>,
+    /// Sent when a pull request is dropped after failing `queue.max_failures` times.
+    #[serde(default = "default_notification")]
+    pub drop: Option<Notification>,
+    /// Sent when a pull request is popped because its checks failed.
+    #[serde(default = "default_notification")]
+    pub checks: Option<Notification>,
 }
 
 // TODO: Add some methods to abstract the notification creation
@@ -53,15 +132,25 @@ impl Default for Queue {
         Self {
             limit: 10,
             interval_in_minutes: 5,
+            when_full: WhenFull::default(),
+            max_failures: default_max_failures(),
         }
     }
 }
 
+impl Default for WhenFull {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
 impl Default for Merger {
     fn default() -> Self {
         Self {
             title: None,
             message: None,
+            method: MergeMethod::default(),
+            title_with_number: false,
         }
     }
 }
@@ -70,17 +159,27 @@ impl Default for Notifier {
     fn default() -> Self {
         Self {
             pop: None,
-            merge: Some(Notification {
-                enabled: true,
-                title: String::new(),
-                message: None,
-                icon: None,
-            }),
+            merge: default_notification(),
             update: None,
+            drop: default_notification(),
+            checks: default_notification(),
         }
     }
 }
 
+fn default_notification() -> Option<Notification> {
+    Some(Notification {
+        enabled: true,
+        title: String::new(),
+        message: None,
+        icon: None,
+    })
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
 /// `Configuration` implements `Default`
 impl Default for Configuration {
     fn default() -> Self {
@@ -88,8 +187,80 @@ impl Default for Configuration {
             queue: Queue::default(),
             merger: Merger::default(),
             notifier: Notifier::default(),
+            labels: None,
+            watch: Vec::new(),
+            repositories: Vec::new(),
+        }
+    }
+}
+
+impl Configuration {
+    /// The settings of the first `[[repository]]` matching `owner/repo`.
+    pub fn repository(&self, owner: &str, repo: &str) -> Option<&RepositorySettings> {
+        let name = format!("{}/{}", owner, repo);
+
+        self.repositories
+            .iter()
+            .find(|settings| glob_match(&settings.name, &name))
+    }
+
+    /// The merge method configured for `owner/repo`.
+    pub fn merge_method(&self, owner: &str, repo: &str) -> MergeMethod {
+        self.repository(owner, repo)
+            .and_then(|settings| settings.method)
+            .unwrap_or(self.merger.method)
+    }
+
+    /// Whether pull requests are queued from watched repositories or labels.
+    pub fn discovers(&self) -> bool {
+        !self.watch.is_empty() || self.labels.is_some()
+    }
+
+    /// Whether the pull requests of `owner/repo` are updated and merged one at a time.
+    pub fn serial(&self, owner: &str, repo: &str) -> bool {
+        self.repository(owner, repo)
+            .map_or(false, |settings| settings.serial)
+    }
+
+    /// The checks configured to pass before merging pull requests of `owner/repo`.
+    pub fn required_checks(&self, owner: &str, repo: &str) -> &[String] {
+        self.repository(owner, repo)
+            .map_or(&[], |settings| &settings.required_checks)
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any number of characters
+/// and `?` a single one. Matching ignores case like GitHub does for repository names.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // The last `*` seen and the position in `text` it currently matches up to.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
         }
     }
+
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 pub fn load() -> Result<Configuration> {
@@ -104,6 +275,71 @@ pub fn load() -> Result<Configuration> {
     }
 }
 
+/// Checks a loaded configuration for values the agent can't run with.
+pub fn validate(config: &Configuration) -> Result<()> {
+    if config.queue.interval_in_minutes == 0 {
+        bail!("queue.interval_in_minutes must be at least 1");
+    }
+
+    if config.queue.limit == 0 {
+        bail!("queue.limit must be at least 1");
+    }
+
+    if config.queue.max_failures == 0 {
+        bail!("queue.max_failures must be at least 1");
+    }
+
+    for template in config.merger.title.iter().chain(&config.merger.message) {
+        crate::template::validate(template)?;
+    }
+
+    for watch in &config.watch {
+        if watch.authors.is_empty() {
+            bail!("watch needs at least one author");
+        }
+
+        validate_repositories("watch", &watch.repositories)?;
+    }
+
+    if let Some(labels) = &config.labels {
+        if labels.label.is_empty() {
+            bail!("labels.label must not be empty");
+        }
+
+        validate_repositories("labels", &labels.repositories)?;
+    }
+
+    for settings in &config.repositories {
+        if settings.name.split('/').count() != 2 {
+            bail!(
+                "repository name {} must match owner/repo, e.g. my-org/*",
+                settings.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_repositories(section: &str, repositories: &[String]) -> Result<()> {
+    if repositories.is_empty() {
+        bail!("{} needs at least one repository", section);
+    }
+
+    for repository in repositories {
+        let parts: Vec<&str> = repository.split('/').collect();
+        if parts.len() > 2 || parts.iter().any(|part| part.is_empty()) {
+            bail!(
+                "{} repository {} must be owner/repo or an owner",
+                section,
+                repository
+            );
+        }
+    }
+
+    Ok(())
+}
+
 pub fn store(config: &Configuration) -> Result<()> {
     let path = config_path();
     let content = toml::to_string(config).unwrap();
@@ -124,7 +360,7 @@ fn ensure_folder(path: &String) -> Result<()> {
     Ok(())
 }
 
-fn config_path() -> String {
+pub fn config_path() -> String {
     let package_name = env!("CARGO_PKG_NAME");
 
     format!(
@@ -147,6 +383,125 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_validate() {
+        assert!(validate(&Configuration::default()).is_ok());
+
+        let mut config = Configuration::default();
+        config.queue.interval_in_minutes = 0;
+        assert!(validate(&config).is_err());
+
+        let mut config = Configuration::default();
+        config.queue.limit = 0;
+        assert!(validate(&config).is_err());
+
+        let mut config = Configuration::default();
+        config.watch.push(Watch {
+            repositories: vec!["Liberatys/rusty-merge".into(), "Liberatys".into()],
+            authors: vec!["dependabot".into()],
+            labels: Vec::new(),
+        });
+        assert!(validate(&config).is_ok());
+
+        config.watch[0]
+            .repositories
+            .push("Liberatys/rusty-merge/pull".into());
+        assert!(validate(&config).is_err());
+
+        config.watch[0].repositories.clear();
+        assert!(validate(&config).is_err());
+
+        let mut config = Configuration::default();
+        config.merger.title = Some("{title} (#{pull_request})".into());
+        assert!(validate(&config).is_err());
+
+        config.merger.title = Some("chore: bump {{deps}}".into());
+        assert!(validate(&config).is_ok());
+
+        let mut config = Configuration::default();
+        config.labels = Some(Labels {
+            repositories: vec!["Liberatys".into()],
+            label: String::new(),
+            merging: None,
+        });
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_when_full_defaults_to_reject() -> Result<()> {
+        let config: Configuration =
+            toml::from_str("[queue]\nlimit = 10\ninterval_in_minutes = 5\n[merger]\n[notifier]\n")?;
+        assert_eq!(config.queue.when_full, WhenFull::Reject);
+
+        let config: Configuration = toml::from_str(
+            "[queue]\nlimit = 10\ninterval_in_minutes = 5\nwhen_full = \"evict_oldest\"\n[merger]\n[notifier]\n",
+        )?;
+        assert_eq!(config.queue.when_full, WhenFull::EvictOldest);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Liberatys/rusty-merge", "liberatys/Rusty-Merge"));
+        assert!(glob_match("Liberatys/*", "Liberatys/rusty-merge"));
+        assert!(glob_match("*/rusty-*", "Liberatys/rusty-merge"));
+        assert!(glob_match("Liberatys/rusty-merg?", "Liberatys/rusty-merge"));
+        assert!(glob_match("*", ""));
+
+        assert!(!glob_match("Liberatys/*", "octocat/rusty-merge"));
+        assert!(!glob_match("Liberatys/rusty", "Liberatys/rusty-merge"));
+        assert!(!glob_match(
+            "Liberatys/rusty-merge?",
+            "Liberatys/rusty-merge"
+        ));
+    }
+
+    #[test]
+    fn test_merge_method_overrides() -> Result<()> {
+        let config: Configuration = toml::from_str(
+            r#"
+            [queue]
+            limit = 10
+            interval_in_minutes = 5
+
+            [merger]
+            method = "merge"
+
+            [notifier]
+
+            [[repository]]
+            name = "Liberatys/rusty-*"
+            method = "rebase"
+            serial = true
+            required_checks = ["build"]
+
+            [[repository]]
+            name = "Liberatys/*"
+            "#,
+        )?;
+
+        assert_eq!(
+            config.merge_method("Liberatys", "rusty-merge"),
+            MergeMethod::Rebase
+        );
+        assert_eq!(
+            config.merge_method("Liberatys", "other"),
+            MergeMethod::Merge
+        );
+        assert_eq!(config.merge_method("octocat", "other"), MergeMethod::Merge);
+        assert!(config.serial("Liberatys", "rusty-merge"));
+        assert!(!config.serial("Liberatys", "other"));
+        assert_eq!(
+            config.required_checks("Liberatys", "rusty-merge"),
+            ["build".to_string()]
+        );
+        assert!(config.required_checks("octocat", "other").is_empty());
+        assert!(validate(&config).is_ok());
+
+        Ok(())
+    }
+
     #[test]
     fn test_store() -> Result<()> {
         if Path::new(&config_path()).exists() {
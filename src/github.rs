@@ -0,0 +1,175 @@
+use crate::config::{Labels, Watch};
+use crate::pull_request::PullRequest;
+use anyhow::{bail, Result};
+use octocrab::Octocrab;
+
+/// A pull request found through the GitHub search.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub pull_request: PullRequest,
+    pub title: String,
+}
+
+/// Creates a GitHub client authenticated with `GITHUB_API_TOKEN`.
+pub fn client() -> Result<Octocrab> {
+    match std::env::var("GITHUB_API_TOKEN") {
+        Ok(token) => Ok(Octocrab::builder().personal_token(token).build()?),
+        Err(_) => bail!("Invalid GITHUB_API_TOKEN"),
+    }
+}
+
+/// Runs a GitHub issue search restricted to pull requests and returns all matches.
+pub async fn search_pull_requests(octocrab: &Octocrab, query: &str) -> Result<Vec<SearchResult>> {
+    let page = octocrab
+        .search()
+        .issues_and_pull_requests(&pull_request_query(query))
+        .per_page(100u8)
+        .send()
+        .await?;
+
+    let mut results = Vec::new();
+    for issue in octocrab.all_pages(page).await? {
+        if issue.pull_request.is_none() {
+            continue;
+        }
+
+        results.push(SearchResult {
+            pull_request: PullRequest::new(issue.html_url.to_string())?,
+            title: issue.title,
+        });
+    }
+
+    Ok(results)
+}
+
+/// The search queries finding the open pull requests of a watch, one per author.
+pub fn watch_queries(watch: &Watch) -> Vec<String> {
+    let scope = open_pull_requests(&watch.repositories, &watch.labels);
+
+    watch
+        .authors
+        .iter()
+        .map(|author| format!("{} author:{}", scope, author_qualifier(author)))
+        .collect()
+}
+
+/// The search query finding the open pull requests carrying the queueing label.
+pub fn label_query(labels: &Labels) -> String {
+    open_pull_requests(&labels.repositories, &[labels.label.clone()])
+}
+
+fn open_pull_requests(repositories: &[String], labels: &[String]) -> String {
+    let mut query = String::from("is:pr is:open archived:false");
+
+    for repository in repositories {
+        let qualifier = if repository.contains('/') {
+            "repo"
+        } else {
+            "user"
+        };
+        query.push_str(&format!(" {}:{}", qualifier, repository));
+    }
+
+    for label in labels {
+        query.push_str(&format!(" label:\"{}\"", label));
+    }
+
+    query
+}
+
+pub async fn add_label(octocrab: &Octocrab, pull_request: &PullRequest, label: &str) -> Result<()> {
+    octocrab
+        .issues(&pull_request.owner, &pull_request.repo)
+        .add_labels(pull_request.request, &[label.to_string()])
+        .await?;
+
+    Ok(())
+}
+
+pub async fn remove_label(
+    octocrab: &Octocrab,
+    pull_request: &PullRequest,
+    label: &str,
+) -> Result<()> {
+    octocrab
+        .issues(&pull_request.owner, &pull_request.repo)
+        .remove_label(pull_request.request, label)
+        .await?;
+
+    Ok(())
+}
+
+/// Bots open their pull requests as apps, which the search only finds as `app/<name>`.
+fn author_qualifier(author: &str) -> String {
+    let login = author.strip_suffix("[bot]").unwrap_or(author);
+
+    match login {
+        "dependabot" | "depfu" | "renovate" => format!("app/{}", login),
+        _ if login != author => format!("app/{}", login),
+        _ => author.to_string(),
+    }
+}
+
+/// Restricts a search query to pull requests, as the search covers issues as well.
+fn pull_request_query(query: &str) -> String {
+    let restricted = query
+        .split_whitespace()
+        .any(|term| term == "is:pr" || term == "type:pr");
+
+    if restricted {
+        query.to_string()
+    } else {
+        format!("is:pr {}", query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pull_request_query() {
+        assert_eq!(
+            pull_request_query("author:app/dependabot is:open"),
+            "is:pr author:app/dependabot is:open"
+        );
+        assert_eq!(
+            pull_request_query("type:pr repo:org/app"),
+            "type:pr repo:org/app"
+        );
+        assert_eq!(pull_request_query("is:pr is:open"), "is:pr is:open");
+    }
+
+    #[test]
+    fn test_watch_queries() {
+        let watch = Watch {
+            repositories: vec!["Liberatys/rusty-merge".into(), "octocat".into()],
+            authors: vec!["dependabot".into(), "my-bot[bot]".into(), "octocat".into()],
+            labels: vec!["ready to merge".into()],
+        };
+
+        let scope = "is:pr is:open archived:false repo:Liberatys/rusty-merge user:octocat label:\"ready to merge\"";
+        assert_eq!(
+            watch_queries(&watch),
+            vec![
+                format!("{} author:app/dependabot", scope),
+                format!("{} author:app/my-bot", scope),
+                format!("{} author:octocat", scope),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_query() {
+        let labels = Labels {
+            repositories: vec!["Liberatys/rusty-merge".into()],
+            label: "automerge".into(),
+            merging: None,
+        };
+
+        assert_eq!(
+            label_query(&labels),
+            "is:pr is:open archived:false repo:Liberatys/rusty-merge label:\"automerge\""
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Context, Result};
 use clap_complete::{generate, Shell};
 use std::io;
-use std::sync::Mutex;
+use tokio::sync::Mutex;
 
 #[macro_use]
 extern crate lazy_static;
@@ -10,19 +10,27 @@ mod agent;
 mod client;
 mod commands;
 mod config;
+mod github;
 mod notifier;
+mod output;
 mod pull_request;
 mod runner;
+mod state;
+mod template;
+mod util;
 
+use crate::output::Output;
 use crate::pull_request::PullRequest;
+use clap::ArgMatches;
 use commands::app;
 
 lazy_static! {
     static ref REPOS: Mutex<Vec<PullRequest>> = Mutex::new(Vec::new());
+    /// Pull requests popped by hand, kept out of the queue when watched repositories are polled.
+    static ref DISMISSED: Mutex<Vec<PullRequest>> = Mutex::new(Vec::new());
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
 
     let mut app = app();
@@ -43,6 +51,17 @@ async fn main() -> Result<(), anyhow::Error> {
         }
     }
 
+    let output = Output::from_matches(&matches);
+    match run(&matches) {
+        Err(err) if output == Output::Json => {
+            output::error(&err)?;
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<()> {
     if let Some(("agent", matches)) = matches.subcommand() {
         return commands::agent(matches);
     }
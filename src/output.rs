@@ -0,0 +1,597 @@
+use crate::agent::{
+    Entry, FailureKind, ForceOutcome, ItemResult, PopOutcome, PushOutcome, Run, Status,
+};
+use crate::pull_request::State;
+use crate::util::unix_timestamp;
+use anyhow::{bail, Result};
+use clap::ArgMatches;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// How commands print their results, chosen with the global `--output` option.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// Sentences for humans.
+    Text,
+    /// A single JSON document on stdout, errors as `{"error": ...}` on stderr.
+    Json,
+    /// Aligned columns.
+    Table,
+}
+
+impl Output {
+    /// The output given to the innermost subcommand of `matches`,
+    /// which sees the option wherever it was given.
+    pub fn from_matches(mut matches: &ArgMatches) -> Self {
+        while let Some((_, subcommand)) = matches.subcommand() {
+            matches = subcommand;
+        }
+
+        matches
+            .value_of("output")
+            .and_then(|output| output.parse().ok())
+            .unwrap_or(Self::Text)
+    }
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(output: &str) -> Result<Self> {
+        match output {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "table" => Ok(Self::Table),
+            _ => bail!("Invalid output {}, expected text, json or table", output),
+        }
+    }
+}
+
+/// A queued pull request in JSON output.
+#[derive(Serialize)]
+struct EntryJson<'a> {
+    url: &'a str,
+    owner: &'a str,
+    repo: &'a str,
+    number: u64,
+    state: State,
+    reason: Option<&'a str>,
+    failures: u32,
+    queued_at: Option<u64>,
+    last_checked: Option<u64>,
+}
+
+/// The outcome for a single pull request of `push` or `pop` in JSON output.
+/// `outcome` is `null` if the pull request failed with `error`.
+#[derive(Serialize)]
+struct ItemJson<'a> {
+    url: &'a str,
+    outcome: Option<&'static str>,
+    /// The pull request evicted from a full queue.
+    evicted: Option<&'a str>,
+    error: Option<String>,
+}
+
+/// The outcome of `clear` or `force` in JSON output.
+#[derive(Serialize)]
+struct OutcomeJson {
+    outcome: &'static str,
+}
+
+#[derive(Serialize)]
+struct StatusJson<'a> {
+    protocol: u32,
+    pid: u32,
+    uptime_in_seconds: u64,
+    config_path: &'a str,
+    queue_length: usize,
+    running: bool,
+    last_run: Option<RunJson<'a>>,
+    next_run: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct RunJson<'a> {
+    started_at: u64,
+    finished_at: Option<u64>,
+    /// `null` while the run is in progress.
+    succeeded: Option<bool>,
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ErrorJson {
+    error: String,
+}
+
+pub fn list(output: Output, entries: &[Entry]) -> Result<()> {
+    let now = unix_timestamp();
+
+    match output {
+        Output::Text => {
+            println!("Queue:");
+            for entry in entries {
+                println!("{}", format_entry(entry, now));
+            }
+        }
+        Output::Json => println!("{}", serde_json::to_string_pretty(&entries_json(entries))?),
+        Output::Table => {
+            let rows = entries
+                .iter()
+                .map(|entry| {
+                    vec![
+                        format!("{}/{}", entry.owner, entry.repo),
+                        entry.number.to_string(),
+                        entry.state.to_string(),
+                        entry
+                            .queued_at
+                            .map(|queued_at| format_duration(now.saturating_sub(queued_at)))
+                            .unwrap_or_else(|| "-".into()),
+                        entry.reason.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            print!(
+                "{}",
+                table(&["REPOSITORY", "NUMBER", "STATE", "AGE", "REASON"], rows)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn entries_json(entries: &[Entry]) -> Vec<EntryJson<'_>> {
+    entries
+        .iter()
+        .map(|entry| EntryJson {
+            url: &entry.url,
+            owner: &entry.owner,
+            repo: &entry.repo,
+            number: entry.number,
+            state: entry.state,
+            reason: entry.reason.as_deref(),
+            failures: entry.failures,
+            queued_at: entry.queued_at,
+            last_checked: entry.last_checked,
+        })
+        .collect()
+}
+
+pub fn status(output: Output, status: &Status) -> Result<()> {
+    if output == Output::Json {
+        let status = StatusJson {
+            protocol: status.protocol,
+            pid: status.pid,
+            uptime_in_seconds: status.uptime_in_seconds,
+            config_path: &status.config_path,
+            queue_length: status.queue_length,
+            running: status.running,
+            last_run: status.last_run.as_ref().map(|run| RunJson {
+                started_at: run.started_at,
+                finished_at: run.finished_at,
+                succeeded: run.result.as_ref().map(|result| result.is_ok()),
+                error: match &run.result {
+                    Some(Err(err)) => Some(err),
+                    _ => None,
+                },
+            }),
+            next_run: status.next_run,
+        };
+        println!("{}", serde_json::to_string_pretty(&status)?);
+
+        return Ok(());
+    }
+
+    // The text is laid out as a table already.
+    let now = unix_timestamp();
+    let rows = vec![
+        vec!["Protocol:".into(), status.protocol.to_string()],
+        vec!["Pid:".into(), status.pid.to_string()],
+        vec!["Uptime:".into(), format_duration(status.uptime_in_seconds)],
+        vec!["Configuration:".into(), status.config_path.clone()],
+        vec![
+            "Queue:".into(),
+            format!("{} pull request(s)", status.queue_length),
+        ],
+        vec![
+            "Last run:".into(),
+            status
+                .last_run
+                .as_ref()
+                .map(|run| format_run(run, now))
+                .unwrap_or_else(|| "never".into()),
+        ],
+        vec![
+            "Running:".into(),
+            if status.running { "yes" } else { "no" }.into(),
+        ],
+        vec![
+            "Next run:".into(),
+            status
+                .next_run
+                .map(|next| format!("in {}", format_duration(next.saturating_sub(now))))
+                .unwrap_or_else(|| "not scheduled".into()),
+        ],
+    ];
+    print!("{}", align(&rows));
+
+    Ok(())
+}
+
+pub fn pushed(output: Output, results: &[ItemResult<PushOutcome>]) -> Result<()> {
+    let outcome = |outcome: &PushOutcome| match outcome {
+        PushOutcome::Added => "added",
+        PushOutcome::Evicted(_) => "evicted",
+        PushOutcome::Duplicate => "duplicate",
+        PushOutcome::Updated => "updated",
+    };
+
+    match output {
+        Output::Text => {
+            for item in results {
+                match &item.result {
+                    Ok(PushOutcome::Added) => println!("Added {} to the queue", item.url),
+                    Ok(PushOutcome::Evicted(evicted)) => println!(
+                        "Added {} to the queue, evicted {} as the queue is full",
+                        item.url, evicted
+                    ),
+                    Ok(PushOutcome::Duplicate) => println!("{} is already queued", item.url),
+                    Ok(PushOutcome::Updated) => {
+                        println!("{} is already queued, updated its options", item.url)
+                    }
+                    Err(failure) => {
+                        eprintln!("Failed to add {}: {}", item.url, describe(failure))
+                    }
+                }
+            }
+        }
+        Output::Json => {
+            let items: Vec<ItemJson> = results
+                .iter()
+                .map(|item| ItemJson {
+                    url: &item.url,
+                    outcome: item.result.as_ref().ok().map(outcome),
+                    evicted: match &item.result {
+                        Ok(PushOutcome::Evicted(evicted)) => Some(evicted),
+                        _ => None,
+                    },
+                    error: item.result.as_ref().err().map(describe),
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
+        }
+        Output::Table => print!("{}", items_table(results, outcome)),
+    }
+
+    Ok(())
+}
+
+pub fn popped(output: Output, results: &[ItemResult<PopOutcome>]) -> Result<()> {
+    let outcome = |outcome: &PopOutcome| match outcome {
+        PopOutcome::Removed => "removed",
+        PopOutcome::NotFound => "not_found",
+    };
+
+    match output {
+        Output::Text => {
+            for item in results {
+                match &item.result {
+                    Ok(PopOutcome::Removed) => println!("Removed {} from the queue", item.url),
+                    Ok(PopOutcome::NotFound) => eprintln!("{} is not queued", item.url),
+                    Err(failure) => {
+                        eprintln!("Failed to remove {}: {}", item.url, describe(failure))
+                    }
+                }
+            }
+        }
+        Output::Json => {
+            let items: Vec<ItemJson> = results
+                .iter()
+                .map(|item| ItemJson {
+                    url: &item.url,
+                    outcome: item.result.as_ref().ok().map(outcome),
+                    evicted: None,
+                    error: item.result.as_ref().err().map(describe),
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&items)?);
+        }
+        Output::Table => print!("{}", items_table(results, outcome)),
+    }
+
+    Ok(())
+}
+
+pub fn cleared(output: Output) -> Result<()> {
+    match output {
+        Output::Json => println!("{}", outcome_json("cleared")?),
+        Output::Text | Output::Table => println!("Cleared the queue"),
+    }
+
+    Ok(())
+}
+
+pub fn forced(output: Output, outcome: ForceOutcome) -> Result<()> {
+    match output {
+        Output::Json => println!("{}", outcome_json(force_outcome(outcome))?),
+        Output::Text | Output::Table => match outcome {
+            ForceOutcome::Started => println!("Started a queue run"),
+            ForceOutcome::FollowUpQueued => {
+                println!("A queue run is in progress, queued a follow-up run")
+            }
+            ForceOutcome::MergedIntoFollowUp => {
+                println!("A queue run is in progress, a follow-up run is queued already")
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn force_outcome(outcome: ForceOutcome) -> &'static str {
+    match outcome {
+        ForceOutcome::Started => "started",
+        ForceOutcome::FollowUpQueued => "follow_up_queued",
+        ForceOutcome::MergedIntoFollowUp => "merged_into_follow_up",
+    }
+}
+
+fn outcome_json(outcome: &'static str) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&OutcomeJson { outcome })?)
+}
+
+/// Prints an error that ended the command as JSON.
+/// Other outputs leave it to `main`, which returns it like any other error.
+pub fn error(err: &anyhow::Error) -> Result<()> {
+    let error = ErrorJson {
+        error: format!("{:#}", err),
+    };
+    eprintln!("{}", serde_json::to_string(&error)?);
+
+    Ok(())
+}
+
+fn items_table<T, F>(results: &[ItemResult<T>], outcome: F) -> String
+where
+    F: Fn(&T) -> &'static str,
+{
+    let rows = results
+        .iter()
+        .map(|item| match &item.result {
+            Ok(result) => vec![item.url.clone(), outcome(result).into(), String::new()],
+            Err(failure) => vec![item.url.clone(), "failed".into(), describe(failure)],
+        })
+        .collect();
+
+    table(&["URL", "OUTCOME", "ERROR"], rows)
+}
+
+/// Lays out `rows` below `headers` in aligned columns.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut all = vec![headers.iter().map(|header| header.to_string()).collect()];
+    all.extend(rows);
+
+    align(&all)
+}
+
+/// Pads every column but the last to its widest cell.
+fn align(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or_default();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut aligned = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| {
+                if column + 1 == row.len() {
+                    cell.clone()
+                } else {
+                    format!("{:width$}", cell, width = widths[column])
+                }
+            })
+            .collect();
+
+        aligned.push_str(cells.join("  ").trim_end());
+        aligned.push('\n');
+    }
+
+    aligned
+}
+
+pub fn describe(failure: &FailureKind) -> String {
+    match failure {
+        FailureKind::InvalidPullRequest(err) => err.clone(),
+        FailureKind::QueueFull(limit) => format!("the queue is full ({} pull requests)", limit),
+        failure => format!("request failed: {:?}", failure),
+    }
+}
+
+fn format_entry(entry: &Entry, now: u64) -> String {
+    let mut line = format!("{} {}", entry.url, entry.state);
+
+    if let Some(reason) = &entry.reason {
+        line.push_str(&format!(": {}", reason));
+    }
+
+    if entry.failures > 0 {
+        line.push_str(&format!(" ({} failures)", entry.failures));
+    }
+
+    if let Some(last_checked) = entry.last_checked {
+        line.push_str(&format!(
+            ", checked {} ago",
+            format_duration(now.saturating_sub(last_checked))
+        ));
+    }
+
+    line
+}
+
+fn format_run(run: &Run, now: u64) -> String {
+    let started = format!(
+        "started {} ago",
+        format_duration(now.saturating_sub(run.started_at))
+    );
+
+    match (&run.finished_at, &run.result) {
+        (Some(finished_at), Some(Ok(()))) => format!(
+            "{}, finished {} ago, succeeded",
+            started,
+            format_duration(now.saturating_sub(*finished_at))
+        ),
+        (Some(finished_at), Some(Err(err))) => format!(
+            "{}, finished {} ago, failed: {}",
+            started,
+            format_duration(now.saturating_sub(*finished_at)),
+            err
+        ),
+        _ => format!("{}, still running", started),
+    }
+}
+
+/// Formats a number of seconds as a short human readable duration like `1h 5m`.
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            url: "https://github.com/Liberatys/rusty-merge/pull/400".into(),
+            owner: "Liberatys".into(),
+            repo: "rusty-merge".into(),
+            number: 400,
+            state: State::Queued,
+            reason: None,
+            queued_at: None,
+            last_checked: None,
+            failures: 0,
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(61), "1m 1s");
+        assert_eq!(format_duration(3_900), "1h 5m");
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let mut entry = entry();
+
+        assert_eq!(
+            format_entry(&entry, 160),
+            "https://github.com/Liberatys/rusty-merge/pull/400 queued"
+        );
+
+        entry.state = State::Failed;
+        entry.reason = Some("merge conflict".into());
+        entry.failures = 2;
+        entry.last_checked = Some(100);
+        assert_eq!(
+            format_entry(&entry, 160),
+            "https://github.com/Liberatys/rusty-merge/pull/400 failed: merge conflict (2 failures), checked 1m 0s ago"
+        );
+    }
+
+    #[test]
+    fn test_format_run() {
+        let run = Run {
+            started_at: 100,
+            finished_at: Some(130),
+            result: Some(Err("boom".into())),
+        };
+
+        assert_eq!(
+            format_run(&run, 160),
+            "started 1m 0s ago, finished 30s ago, failed: boom"
+        );
+    }
+
+    #[test]
+    fn test_table_aligns_columns() {
+        let rows = vec![
+            vec![
+                "Liberatys/rusty-merge".into(),
+                "400".into(),
+                "queued".into(),
+            ],
+            vec!["octocat/hello".into(), "1".into(), "ready to merge".into()],
+        ];
+
+        assert_eq!(
+            table(&["REPOSITORY", "NUMBER", "STATE"], rows),
+            "REPOSITORY             NUMBER  STATE\n\
+             Liberatys/rusty-merge  400     queued\n\
+             octocat/hello          1       ready to merge\n"
+        );
+    }
+
+    #[test]
+    fn test_json_schema() -> Result<()> {
+        let item = ItemJson {
+            url: "https://github.com/Liberatys/rusty-merge/pull/400",
+            outcome: Some("added"),
+            evicted: None,
+            error: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&item)?,
+            r#"{"url":"https://github.com/Liberatys/rusty-merge/pull/400","outcome":"added","evicted":null,"error":null}"#
+        );
+
+        let entry = Entry {
+            state: State::WaitingForChecks,
+            queued_at: Some(100),
+            ..entry()
+        };
+        assert_eq!(
+            serde_json::to_value(entries_json(&[entry]))?,
+            serde_json::json!([{
+                "url": "https://github.com/Liberatys/rusty-merge/pull/400",
+                "owner": "Liberatys",
+                "repo": "rusty-merge",
+                "number": 400,
+                "state": "waiting_for_checks",
+                "reason": null,
+                "failures": 0,
+                "queued_at": 100,
+                "last_checked": null
+            }])
+        );
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&outcome_json(force_outcome(
+                ForceOutcome::FollowUpQueued
+            ))?)?,
+            serde_json::json!({"outcome": "follow_up_queued"})
+        );
+
+        Ok(())
+    }
+}
@@ -1,68 +1,401 @@
-use anyhow::{bail, Result};
+use crate::util::unix_timestamp;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
 
-static EXPECTED_PARTS_OF_RESOURCE: usize = 4;
+static DEFAULT_HOST: &str = "github.com";
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct PullRequest {
     pub url: Option<String>,
+    #[serde(default = "default_host")]
+    pub host: String,
     pub owner: String,
     pub repo: String,
     pub request: u64,
+    /// How the pull request got into the queue.
+    #[serde(default)]
+    pub source: Source,
+    /// How the pull request is merged, given when it was pushed.
+    #[serde(default)]
+    pub options: Options,
+    /// How many queue runs in a row failed to process the pull request.
+    #[serde(default)]
+    pub failures: u32,
+    /// Where the pull request stands, as of its last check by the runner.
+    #[serde(default)]
+    pub state: State,
+    /// Why the pull request is in its `state`, e.g. the error it failed with.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// When the runner last checked the pull request, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub last_checked: Option<u64>,
+    /// When the pull request was queued, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub queued_at: Option<u64>,
+}
+
+/// Where a queued pull request stands on its way to be merged.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Not checked yet, or GitHub is still computing whether it can be merged.
+    Queued,
+    /// Behind its base branch and being updated.
+    Updating,
+    WaitingForChecks,
+    /// Kept from merging, e.g. by missing reviews or as a draft.
+    Blocked,
+    Conflicting,
+    ReadyToMerge,
+    Merging,
+    /// Processing the pull request failed.
+    Failed,
+}
+
+/// Per pull request settings, overriding the configured defaults of the merger.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct Options {
+    pub method: Option<MergeMethod>,
+    pub title: Option<String>,
+    pub message: Option<String>,
+    /// Wait for the branch to be updated instead of updating it with its base.
+    pub no_update: bool,
+    /// Delete the head branch once the pull request is merged.
+    pub delete_branch: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+/// How a pull request got into the queue.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Pushed by hand.
+    Push,
+    /// Found in a watched repository.
+    Watch,
+    /// Labeled for the queue on GitHub.
+    Label,
+}
+
+/// The repository a pull request belongs to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Repository {
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
 }
 
 impl Default for PullRequest {
     fn default() -> Self {
         Self {
             url: None,
+            host: default_host(),
             owner: String::new(),
             repo: String::new(),
             request: 0,
+            source: Source::default(),
+            options: Options::default(),
+            failures: 0,
+            state: State::default(),
+            reason: None,
+            last_checked: None,
+            queued_at: None,
+        }
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Push
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::Queued
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            Self::Queued => "queued",
+            Self::Updating => "updating",
+            Self::WaitingForChecks => "waiting for checks",
+            Self::Blocked => "blocked",
+            Self::Conflicting => "conflicting",
+            Self::ReadyToMerge => "ready to merge",
+            Self::Merging => "merging",
+            Self::Failed => "failed",
+        };
+
+        write!(f, "{}", state)
+    }
+}
+
+impl Default for MergeMethod {
+    fn default() -> Self {
+        Self::Squash
+    }
+}
+
+impl FromStr for MergeMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(method: &str) -> Result<Self> {
+        match method {
+            "merge" => Ok(Self::Merge),
+            "squash" => Ok(Self::Squash),
+            "rebase" => Ok(Self::Rebase),
+            _ => bail!(
+                "Invalid merge method {}, expected merge, squash or rebase",
+                method
+            ),
         }
     }
 }
 
 impl PullRequest {
+    /// Parses a pull request reference, either a url like `github.com/owner/repo/pull/123`
+    /// or `owner/repo#123`.
     pub fn new(resource: String) -> Result<Self> {
-        if !Self::valid(&resource) {
-            bail!("Invalid url for pull request");
-        }
+        Self::parse(&resource, || {
+            Err(anyhow!(
+                "{} has no repository, use owner/repo#number or the url of the pull request",
+                resource.trim()
+            ))
+        })
+    }
 
-        let resource_parts: Vec<&str> = resource.split("/").collect();
-        let length = resource_parts.len();
+    /// Like `new`, but also resolves `#123` and `123` to the repository checked out in the
+    /// current directory, as found through its `origin` remote.
+    /// Only meant for clients, the working directory of the agent is unrelated.
+    pub fn resolve(resource: &str) -> Result<Self> {
+        Self::parse(resource, Repository::origin)
+    }
 
-        Ok(Self {
-            url: Some(resource.clone()),
-            owner: resource_parts[length - 4].to_string(),
-            repo: resource_parts[length - 3].to_string(),
-            request: resource_parts[length - 1].parse::<u64>()?,
+    /// Whether `resource` is a well-formed pull request reference.
+    /// Short references are not resolved, so their repository may still be unknown.
+    pub fn valid(resource: &str) -> bool {
+        Self::parse(resource, || {
+            Ok(Repository {
+                host: default_host(),
+                owner: String::new(),
+                repo: String::new(),
+            })
         })
+        .is_ok()
     }
 
-    pub fn valid(resource: &str) -> bool {
-        let normalized_resource = resource
-            .replace("https://github.com/", "")
-            .replace("//", "/")
-            .replace("github.com/", "");
+    fn parse<F>(resource: &str, origin: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Repository>,
+    {
+        let resource = resource.trim();
 
-        if !normalized_resource.contains("pull") {
-            return false;
+        let number = resource.strip_prefix('#').unwrap_or(resource);
+        if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(Self::from_parts(origin()?, parse_number(number)?));
         }
 
-        if normalized_resource.matches("/").count() < (EXPECTED_PARTS_OF_RESOURCE - 1) {
-            return false;
+        if let Some((repository, number)) = resource.split_once('#') {
+            let parts: Vec<&str> = repository.split('/').collect();
+
+            if parts.len() != 2 || parts.iter().any(|part| part.is_empty()) {
+                bail!(
+                    "Invalid pull request reference {}, expected owner/repo#number",
+                    resource
+                );
+            }
+
+            let repository = Repository {
+                host: default_host(),
+                owner: parts[0].to_string(),
+                repo: parts[1].to_string(),
+            };
+
+            return Ok(Self::from_parts(repository, parse_number(number)?));
         }
 
-        true
+        Self::parse_url(resource)
+    }
+
+    /// Parses `[https://][host/]owner/repo/pull/number[/...]`.
+    fn parse_url(resource: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid url for pull request {}", resource);
+
+        let path = strip_scheme(resource);
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let pull = parts
+            .iter()
+            .position(|part| *part == "pull")
+            .ok_or_else(invalid)?;
+
+        // Owners can't contain dots, which tells a host apart from an owner.
+        let (host, owner, repo) = match pull {
+            2 if !parts[0].contains('.') => (default_host(), parts[0], parts[1]),
+            3 if parts[0].contains('.') => (supported_host(parts[0])?, parts[1], parts[2]),
+            _ => return Err(invalid()),
+        };
+        let number = parts.get(pull + 1).ok_or_else(invalid)?;
+
+        let repository = Repository {
+            host,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+        };
+
+        Ok(Self::from_parts(repository, parse_number(number)?))
+    }
+
+    fn from_parts(repository: Repository, request: u64) -> Self {
+        Self {
+            url: Some(format!(
+                "https://{}/{}/{}/pull/{}",
+                repository.host, repository.owner, repository.repo, request
+            )),
+            host: repository.host,
+            owner: repository.owner,
+            repo: repository.repo,
+            request,
+            source: Source::default(),
+            options: Options::default(),
+            failures: 0,
+            state: State::default(),
+            reason: None,
+            last_checked: None,
+            queued_at: None,
+        }
+    }
+
+    /// Records the outcome of a check by the runner.
+    pub fn set_state(&mut self, state: State, reason: Option<String>) {
+        self.state = state;
+        self.reason = reason;
+        self.last_checked = Some(unix_timestamp());
     }
 
+    /// Whether both refer to the same pull request, regardless of how it was spelled.
+    /// GitHub treats owner and repository names case-insensitively.
     pub fn same(&self, other: &PullRequest) -> bool {
-        self.url == other.url
+        self.request == other.request && self.same_repository(other)
+    }
+
+    /// Whether both belong to the same repository.
+    pub fn same_repository(&self, other: &PullRequest) -> bool {
+        self.host.eq_ignore_ascii_case(&other.host)
+            && self.owner.eq_ignore_ascii_case(&other.owner)
+            && self.repo.eq_ignore_ascii_case(&other.repo)
+    }
+}
+
+impl Repository {
+    /// The repository behind the `origin` remote of the git checkout in the current directory.
+    pub fn origin() -> Result<Self> {
+        let output = Command::new("git")
+            .args(["remote", "get-url", "origin"])
+            .output()
+            .with_context(|| "failed to run git")?;
+
+        if !output.status.success() {
+            bail!("No origin remote found, use owner/repo#number or the url of the pull request");
+        }
+
+        Self::from_remote(String::from_utf8_lossy(&output.stdout).trim())
+    }
+
+    /// Parses a git remote url, either `[ssh|https]://[user@]host[:port]/owner/repo[.git]`
+    /// or the scp-like `[user@]host:owner/repo[.git]` used for SSH.
+    pub fn from_remote(remote: &str) -> Result<Self> {
+        let invalid = || anyhow!("Unsupported remote url {}", remote);
+
+        let has_scheme = remote.contains("://");
+        let address = strip_scheme(remote);
+        let address = match (address.find('@'), address.find('/')) {
+            (Some(at), Some(slash)) if at < slash => &address[at + 1..],
+            (Some(at), None) => &address[at + 1..],
+            _ => address,
+        };
+
+        let (host, path) = if has_scheme {
+            let (authority, path) = address.split_once('/').ok_or_else(invalid)?;
+            let host = authority
+                .split_once(':')
+                .map_or(authority, |(host, _port)| host);
+            (host, path)
+        } else {
+            address.split_once(':').ok_or_else(invalid)?
+        };
+
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let parts: Vec<&str> = path.split('/').collect();
+
+        if host.is_empty() || parts.len() != 2 || parts.iter().any(|part| part.is_empty()) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: supported_host(host)?,
+            owner: parts[0].to_string(),
+            repo: parts[1].to_string(),
+        })
+    }
+}
+
+fn strip_scheme(resource: &str) -> &str {
+    resource
+        .split_once("://")
+        .map_or(resource, |(_scheme, rest)| rest)
+}
+
+fn parse_number(number: &str) -> Result<u64> {
+    number
+        .parse::<u64>()
+        .with_context(|| format!("Invalid pull request number {}", number))
+}
+
+fn default_host() -> String {
+    DEFAULT_HOST.to_string()
+}
+
+/// `host` in lowercase if it is GitHub, the only host the GitHub client talks to.
+fn supported_host(host: &str) -> Result<String> {
+    if !host.eq_ignore_ascii_case(DEFAULT_HOST) {
+        bail!(
+            "Unsupported host {}, only {} is supported",
+            host,
+            DEFAULT_HOST
+        );
+    }
+
+    Ok(host.to_lowercase())
+}
+
+#[cfg(test)]
+impl PullRequest {
+    /// The pull request `number` of this repository, for tests.
+    pub fn numbered(number: u64) -> Self {
+        Self::new(format!("Liberatys/rusty-merge#{}", number)).unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use anyhow::Result;
 
     #[test]
     fn test_valid_with_full_url() {
@@ -112,30 +445,182 @@ mod tests {
         );
     }
 
+    fn origin() -> Result<Repository> {
+        Ok(Repository {
+            host: "github.com".into(),
+            owner: "Liberatys".into(),
+            repo: "rusty-merge".into(),
+        })
+    }
+
+    #[test]
+    fn test_parse_short_references() -> Result<()> {
+        let expected =
+            PullRequest::new("https://github.com/Liberatys/rusty-merge/pull/400".into())?;
+
+        for reference in &["Liberatys/rusty-merge#400", "#400", "400", " 400\n"] {
+            assert_eq!(PullRequest::parse(reference, origin)?, expected);
+        }
+
+        let other = PullRequest::parse("octocat/hello-world#1", origin)?;
+        assert_eq!(other.owner, "octocat");
+        assert_eq!(other.repo, "hello-world");
+        assert_eq!(other.request, 1);
+
+        // Never looks for a git checkout.
+        assert!(PullRequest::new("#400".into()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_url_with_trailing_segments() -> Result<()> {
+        let pull_request = PullRequest::parse(
+            "https://github.com/Liberatys/rusty-merge/pull/400/files",
+            origin,
+        )?;
+
+        assert_eq!(pull_request.request, 400);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_references() {
+        for reference in &[
+            "",
+            "#",
+            "#abc",
+            "rusty-merge#400",
+            "Liberatys/rusty-merge/extra#400",
+            "Liberatys/rusty-merge#",
+            "Liberatys/rusty-merge/pull/",
+            "Liberatys/rusty-merge/pull/abc",
+            "a/Liberatys/rusty-merge/pull/400",
+        ] {
+            assert!(
+                PullRequest::parse(reference, origin).is_err(),
+                "{}",
+                reference
+            );
+        }
+    }
+
+    #[test]
+    fn test_short_reference_without_origin() {
+        assert!(PullRequest::parse("#400", || bail!("no origin")).is_err());
+    }
+
     #[test]
-    fn test_same() {
+    fn test_repository_from_remote() -> Result<()> {
+        let expected = origin()?;
+
+        for remote in &[
+            "git@github.com:Liberatys/rusty-merge.git",
+            "git@github.com:Liberatys/rusty-merge",
+            "ssh://git@github.com/Liberatys/rusty-merge.git",
+            "ssh://git@github.com:22/Liberatys/rusty-merge.git",
+            "https://github.com/Liberatys/rusty-merge.git",
+            "https://github.com/Liberatys/rusty-merge/",
+            "https://user@GitHub.com/Liberatys/rusty-merge",
+        ] {
+            assert_eq!(Repository::from_remote(remote)?, expected, "{}", remote);
+        }
+
+        assert!(Repository::from_remote("/srv/git/rusty-merge.git").is_err());
+        assert!(Repository::from_remote("https://github.com/Liberatys").is_err());
+        assert!(Repository::from_remote("git@git.example.com:Liberatys/rusty-merge.git").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new() -> Result<()> {
+        let pull_request = PullRequest::new("Liberatys/rusty-merge//pull/400".into())?;
+
+        assert_eq!(pull_request.host, "github.com");
+        assert_eq!(pull_request.owner, "Liberatys");
+        assert_eq!(pull_request.repo, "rusty-merge");
+        assert_eq!(pull_request.request, 400);
         assert_eq!(
-            PullRequest {
-                url: Some("://url.com".to_string()),
-                ..PullRequest::default()
-            }
-            .same(&PullRequest {
-                url: Some("https://url.com".to_string()),
-                ..PullRequest::default()
-            }),
-            false
+            pull_request.url,
+            Some("https://github.com/Liberatys/rusty-merge/pull/400".into())
         );
 
-        assert_eq!(
-            PullRequest {
-                url: Some("https://url.com".to_string()),
-                ..PullRequest::default()
-            }
-            .same(&PullRequest {
-                url: Some("https://url.com".to_string()),
-                ..PullRequest::default()
-            }),
-            true
+        let pull_request =
+            PullRequest::new("https://GitHub.com/Liberatys/rusty-merge/pull/400".into())?;
+        assert_eq!(pull_request.host, "github.com");
+
+        // Every request goes to GitHub, which would pick a different pull request.
+        assert!(
+            PullRequest::new("https://git.example.com/Liberatys/rusty-merge/pull/400".into())
+                .is_err()
         );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_without_options() -> Result<()> {
+        let pull_request: PullRequest = serde_json::from_str(
+            r#"{"url": null, "owner": "Liberatys", "repo": "rusty-merge", "request": 400}"#,
+        )?;
+
+        assert_eq!(pull_request.source, Source::Push);
+        assert_eq!(pull_request.options, Options::default());
+        assert_eq!(pull_request.state, State::Queued);
+        assert_eq!(pull_request.last_checked, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_state() -> Result<()> {
+        let mut pull_request = PullRequest::new("Liberatys/rusty-merge#400".into())?;
+
+        pull_request.set_state(State::Blocked, Some("draft".into()));
+
+        assert_eq!(pull_request.state, State::Blocked);
+        assert_eq!(pull_request.reason, Some("draft".into()));
+        assert!(pull_request.last_checked.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_merge_method() -> Result<()> {
+        assert_eq!("rebase".parse::<MergeMethod>()?, MergeMethod::Rebase);
+        assert!("fast-forward".parse::<MergeMethod>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same() -> Result<()> {
+        let pull_request =
+            PullRequest::new("https://github.com/Liberatys/rusty-merge/pull/1".into())?;
+
+        for spelling in &[
+            "github.com/Liberatys/rusty-merge/pull/1",
+            "liberatys/Rusty-Merge//pull/1",
+            "http://github.com/LIBERATYS/rusty-merge/pull/1",
+        ] {
+            assert_eq!(
+                pull_request.same(&PullRequest::new(spelling.to_string())?),
+                true
+            );
+        }
+
+        for other in &[
+            "https://github.com/Liberatys/rusty-merge/pull/2",
+            "https://github.com/Liberatys/other/pull/1",
+        ] {
+            assert_eq!(
+                pull_request.same(&PullRequest::new(other.to_string())?),
+                false
+            );
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,230 @@
+use crate::pull_request::PullRequest;
+use anyhow::Result;
+use octocrab::Octocrab;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// The most items GitHub returns per page.
+const PER_PAGE: usize = 100;
+
+/// Where the checks on the head commit of a pull request stand.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checks {
+    Passed,
+    /// The names of the checks that are still running or haven't reported yet,
+    /// empty if no check reported yet while some were `expected`.
+    Pending(Vec<String>),
+    /// The names of the checks that failed.
+    Failed(Vec<String>),
+}
+
+/// A check run of a GitHub App like GitHub Actions.
+#[derive(Debug, Deserialize)]
+pub struct CheckRun {
+    pub name: String,
+    /// `queued`, `in_progress` or `completed`.
+    pub status: String,
+    /// Set once the check run is `completed`.
+    pub conclusion: Option<String>,
+}
+
+/// A commit status set through the statuses API, e.g. by external CI.
+#[derive(Debug, Deserialize)]
+pub struct CommitStatus {
+    pub context: String,
+    /// `pending`, `success`, `failure` or `error`.
+    pub state: String,
+}
+
+/// Fetches the check runs and commit statuses of the commit `sha` in the repository of
+/// `pull_request` and evaluates them against `required`.
+pub async fn checks(
+    octocrab: &Octocrab,
+    pull_request: &PullRequest,
+    sha: &str,
+    required: &[String],
+    expected: bool,
+) -> Result<Checks> {
+    let commit = format!(
+        "repos/{}/{}/commits/{}",
+        pull_request.owner, pull_request.repo, sha
+    );
+
+    let check_runs: Vec<CheckRun> =
+        all_pages(octocrab, &format!("{}/check-runs", commit), "check_runs").await?;
+    let statuses: Vec<CommitStatus> =
+        all_pages(octocrab, &format!("{}/status", commit), "statuses").await?;
+
+    Ok(evaluate(&check_runs, &statuses, required, expected))
+}
+
+/// Fetches every page of `route`, which answers with `total_count` and the items under `key`.
+async fn all_pages<T: DeserializeOwned>(
+    octocrab: &Octocrab,
+    route: &str,
+    key: &str,
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+
+    for page in 1.. {
+        let mut response: serde_json::Value = octocrab
+            .get(
+                format!("{}?per_page={}&page={}", route, PER_PAGE, page),
+                None::<&()>,
+            )
+            .await?;
+
+        let total = response["total_count"].as_u64().unwrap_or_default() as usize;
+        let page: Vec<T> = serde_json::from_value(response[key].take())?;
+        let last = page.len() < PER_PAGE;
+        items.extend(page);
+
+        if last || items.len() >= total {
+            break;
+        }
+    }
+
+    Ok(items)
+}
+
+/// Evaluates the `required` checks, or all reported ones if none are required.
+/// A required check that hasn't reported yet is pending, failures take precedence.
+/// Without any reported checks, CI may not have picked up the commit yet,
+/// which is waited for if checks are `expected`.
+pub fn evaluate(
+    check_runs: &[CheckRun],
+    statuses: &[CommitStatus],
+    required: &[String],
+    expected: bool,
+) -> Checks {
+    if expected && check_runs.is_empty() && statuses.is_empty() {
+        return Checks::Pending(required.to_vec());
+    }
+
+    let reported = check_runs
+        .iter()
+        .map(|run| {
+            let passed = match run.conclusion.as_deref() {
+                _ if run.status != "completed" => None,
+                Some("success") | Some("neutral") | Some("skipped") => Some(true),
+                _ => Some(false),
+            };
+            (run.name.as_str(), passed)
+        })
+        .chain(statuses.iter().map(|status| {
+            let passed = match status.state.as_str() {
+                "pending" => None,
+                "success" => Some(true),
+                _ => Some(false),
+            };
+            (status.context.as_str(), passed)
+        }))
+        .filter(|(name, _)| required.is_empty() || required.iter().any(|check| check == name));
+
+    let mut failed = Vec::new();
+    let mut pending: Vec<String> = required
+        .iter()
+        .filter(|check| {
+            !check_runs.iter().any(|run| &run.name == *check)
+                && !statuses.iter().any(|status| &status.context == *check)
+        })
+        .cloned()
+        .collect();
+
+    for (name, passed) in reported {
+        match passed {
+            Some(true) => {}
+            Some(false) => failed.push(name.to_string()),
+            None => pending.push(name.to_string()),
+        }
+    }
+
+    if !failed.is_empty() {
+        Checks::Failed(failed)
+    } else if !pending.is_empty() {
+        Checks::Pending(pending)
+    } else {
+        Checks::Passed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_run(name: &str, status: &str, conclusion: Option<&str>) -> CheckRun {
+        CheckRun {
+            name: name.into(),
+            status: status.into(),
+            conclusion: conclusion.map(String::from),
+        }
+    }
+
+    fn commit_status(context: &str, state: &str) -> CommitStatus {
+        CommitStatus {
+            context: context.into(),
+            state: state.into(),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_all_checks() {
+        let check_runs = vec![
+            check_run("build", "completed", Some("success")),
+            check_run("lint", "completed", Some("skipped")),
+        ];
+        let statuses = vec![commit_status("ci/circleci", "success")];
+        assert_eq!(evaluate(&check_runs, &statuses, &[], false), Checks::Passed);
+
+        let statuses = vec![commit_status("ci/circleci", "pending")];
+        assert_eq!(
+            evaluate(&check_runs, &statuses, &[], false),
+            Checks::Pending(vec!["ci/circleci".into()])
+        );
+
+        let check_runs = vec![
+            check_run("build", "in_progress", None),
+            check_run("lint", "completed", Some("failure")),
+        ];
+        assert_eq!(
+            evaluate(&check_runs, &statuses, &[], false),
+            Checks::Failed(vec!["lint".into()])
+        );
+    }
+
+    #[test]
+    fn test_evaluate_nothing_reported() {
+        assert_eq!(evaluate(&[], &[], &[], false), Checks::Passed);
+        assert_eq!(evaluate(&[], &[], &[], true), Checks::Pending(Vec::new()));
+
+        let check_runs = vec![check_run("build", "completed", Some("success"))];
+        assert_eq!(evaluate(&check_runs, &[], &[], true), Checks::Passed);
+    }
+
+    #[test]
+    fn test_evaluate_required_checks() {
+        let check_runs = vec![
+            check_run("build", "completed", Some("success")),
+            check_run("flaky", "completed", Some("failure")),
+        ];
+        let statuses = vec![commit_status("ci/circleci", "error")];
+
+        assert_eq!(
+            evaluate(&check_runs, &statuses, &["build".into()], false),
+            Checks::Passed
+        );
+        assert_eq!(
+            evaluate(
+                &check_runs,
+                &statuses,
+                &["build".into(), "deploy".into()],
+                false
+            ),
+            Checks::Pending(vec!["deploy".into()])
+        );
+        assert_eq!(
+            evaluate(&check_runs, &statuses, &["ci/circleci".into()], false),
+            Checks::Failed(vec!["ci/circleci".into()])
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::pull_request::{MergeMethod, PullRequest};
+use anyhow::Result;
+use octocrab::Octocrab;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the settings of a repository are trusted before they are fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<String, (Instant, AllowedMethods)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The merge methods enabled in the settings of a repository.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllowedMethods {
+    pub merge: bool,
+    pub squash: bool,
+    pub rebase: bool,
+}
+
+impl AllowedMethods {
+    pub fn allows(&self, method: MergeMethod) -> bool {
+        match method {
+            MergeMethod::Merge => self.merge,
+            MergeMethod::Squash => self.squash,
+            MergeMethod::Rebase => self.rebase,
+        }
+    }
+
+    /// `method` if the repository allows it, otherwise the first allowed method
+    /// unless `method` was asked for `explicitly`.
+    pub fn choose(&self, method: MergeMethod, explicitly: bool) -> Option<MergeMethod> {
+        if explicitly {
+            return Some(method).filter(|method| self.allows(*method));
+        }
+
+        [
+            method,
+            MergeMethod::Squash,
+            MergeMethod::Merge,
+            MergeMethod::Rebase,
+        ]
+        .iter()
+        .copied()
+        .find(|method| self.allows(*method))
+    }
+}
+
+/// The merge methods allowed in the repository of `pull_request`,
+/// fetched once per `CACHE_TTL` for each repository.
+pub async fn allowed_methods(
+    octocrab: &Octocrab,
+    pull_request: &PullRequest,
+) -> Result<AllowedMethods> {
+    let key = format!("{}/{}", pull_request.owner, pull_request.repo).to_lowercase();
+
+    if let Some((fetched_at, allowed)) = CACHE.lock().unwrap().get(&key) {
+        if fetched_at.elapsed() < CACHE_TTL {
+            return Ok(*allowed);
+        }
+    }
+
+    let repository = octocrab
+        .repos(&pull_request.owner, &pull_request.repo)
+        .get()
+        .await?;
+
+    // The settings are only visible with push access, assume GitHub's defaults otherwise.
+    let allowed = AllowedMethods {
+        merge: repository.allow_merge_commit.unwrap_or(true),
+        squash: repository.allow_squash_merge.unwrap_or(true),
+        rebase: repository.allow_rebase_merge.unwrap_or(true),
+    };
+
+    CACHE.lock().unwrap().insert(key, (Instant::now(), allowed));
+
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_falls_back_to_allowed_method() {
+        let allowed = AllowedMethods {
+            merge: true,
+            squash: false,
+            rebase: true,
+        };
+
+        assert_eq!(
+            allowed.choose(MergeMethod::Rebase, false),
+            Some(MergeMethod::Rebase)
+        );
+        assert_eq!(
+            allowed.choose(MergeMethod::Squash, false),
+            Some(MergeMethod::Merge)
+        );
+
+        let none = AllowedMethods {
+            merge: false,
+            squash: false,
+            rebase: false,
+        };
+        assert_eq!(none.choose(MergeMethod::Squash, false), None);
+    }
+
+    #[test]
+    fn test_choose_keeps_explicit_method() {
+        let allowed = AllowedMethods {
+            merge: true,
+            squash: false,
+            rebase: true,
+        };
+
+        assert_eq!(
+            allowed.choose(MergeMethod::Rebase, true),
+            Some(MergeMethod::Rebase)
+        );
+        assert_eq!(allowed.choose(MergeMethod::Squash, true), None);
+    }
+}
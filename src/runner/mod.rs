@@ -1,7 +1,9 @@
 mod actions;
+mod checks;
+mod methods;
 mod runner;
 mod util;
 
 pub use actions::Action;
 pub use runner::Runner;
-use util::{should_merge, should_pop, should_update};
+use util::{assess, mergeability, should_pop, Mergeability};
@@ -1,15 +1,38 @@
+use super::checks::{checks, Checks};
+use super::methods::allowed_methods;
 use super::Action;
-use super::{should_merge, should_pop, should_update};
+use super::{assess, mergeability, should_pop, Mergeability};
 use crate::config::{Configuration, Notification};
-use crate::pull_request::PullRequest;
+use crate::pull_request::{MergeMethod, PullRequest, State};
+use crate::template;
+use crate::template::Values;
+use crate::REPOS;
 use anyhow::{bail, Result};
 use log::{info, trace, warn};
 use notify_rust::Timeout;
+use octocrab::models::pulls::MergeableState;
+use octocrab::params::repos::Reference;
 use octocrab::Octocrab;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// The delays between fetching a pull request again while GitHub computes its mergeability.
+const MERGEABILITY_BACKOFF: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(4),
+];
 
 pub struct Runner {
     configuration: Configuration,
     requests_to_remove: Vec<PullRequest>,
+    /// Each pull request of the last `process` with the error it failed with, if any.
+    outcomes: Vec<(PullRequest, Option<String>)>,
+    /// The pull requests dropped by `cleanup` after failing too often.
+    dropped: Vec<PullRequest>,
+    /// The pull requests popped by the last `process` as their checks failed, with the reason.
+    failed_checks: Vec<(PullRequest, String)>,
 }
 
 impl Runner {
@@ -20,75 +43,183 @@ impl Runner {
             Ok(Self {
                 configuration,
                 requests_to_remove: Vec::new(),
+                outcomes: Vec::new(),
+                dropped: Vec::new(),
+                failed_checks: Vec::new(),
             })
         } else {
             bail!("Invalid GITHUB_API_TOKEN")
         }
     }
 
+    /// Processes every pull request on its own, so a failing one doesn't hold up the others.
+    /// Fails if any of them failed, once all are processed.
+    /// In serial repositories only the head of line is processed, the others wait for it.
     pub async fn process(&mut self, pull_requests: Vec<PullRequest>) -> Result<()> {
-        let octocrab = octocrab::instance();
+        self.process_with(pull_requests, |runner, pull_request| {
+            Box::pin(runner.process_pull_request(pull_request))
+        })
+        .await
+    }
+
+    /// `process`, handling each pull request that isn't waiting with `process_one`.
+    async fn process_with<F>(
+        &mut self,
+        pull_requests: Vec<PullRequest>,
+        mut process_one: F,
+    ) -> Result<()>
+    where
+        F: for<'a> FnMut(
+            &'a mut Self,
+            &'a PullRequest,
+        ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>,
+    {
         info!("Processing queue");
 
+        let total = pull_requests.len();
+        // The pull requests of serial repositories that are still queued after processing.
+        let mut heads: Vec<PullRequest> = Vec::new();
         for pull_request in pull_requests {
-            let action: Action = self.detect_action(&pull_request).await?;
+            if let Some(head) = head_of_line(&heads, &pull_request) {
+                let head = head.url.clone().unwrap_or_default();
+                trace!("{:?} waits for {}", &pull_request.url, head);
+                // Waiting is no outcome, it keeps the failures counted so far.
+                Self::set_state(
+                    &pull_request,
+                    State::Queued,
+                    Some(format!("waiting for {} to be merged or popped", head)),
+                )
+                .await;
+                continue;
+            }
+
+            let error = match process_one(self, &pull_request).await {
+                Ok(()) => None,
+                Err(err) => {
+                    warn!("Processing {:?} failed: {}", &pull_request.url, err);
+                    Some(err.to_string())
+                }
+            };
+
+            let removed = self
+                .requests_to_remove
+                .iter()
+                .any(|p| p.same(&pull_request));
+            if !removed
+                && self
+                    .configuration
+                    .serial(&pull_request.owner, &pull_request.repo)
+            {
+                heads.push(pull_request.clone());
+            }
+
+            self.outcomes.push((pull_request, error));
+        }
+
+        let failed = self
+            .outcomes
+            .iter()
+            .filter(|(_, error)| error.is_some())
+            .count();
+        if failed > 0 {
+            bail!("{} of {} pull requests failed", failed, total);
+        }
+
+        Ok(())
+    }
+
+    async fn process_pull_request(&mut self, pull_request: &PullRequest) -> Result<()> {
+        let octocrab = octocrab::instance();
 
-            let to_remove: Option<PullRequest> = match action.clone() {
-                Action::NoOp => None,
-                Action::Pop => Some(pull_request.clone()),
-                Action::Update => {
-                    info!("Updating: {:?}", &pull_request.url);
+        let (action, pr) = self.detect_action(pull_request).await?;
 
-                    octocrab
-                        .pulls(&pull_request.owner, &pull_request.repo)
-                        .update_branch(pull_request.request)
-                        .await?;
+        let to_remove: Option<PullRequest> = match action.clone() {
+            Action::NoOp => None,
+            Action::Pop => Some(pull_request.clone()),
+            Action::Update => {
+                info!("Updating: {:?}", &pull_request.url);
 
-                    None
+                octocrab
+                    .pulls(&pull_request.owner, &pull_request.repo)
+                    .update_branch(pull_request.request)
+                    .await?;
+
+                None
+            }
+            Action::Merge => {
+                info!("Merging: {:?}", &pull_request.url);
+                Self::set_state(pull_request, State::Merging, None).await;
+                // TODO: Extract
+                let options = &pull_request.options;
+                let method = self.merge_method(pull_request).await?;
+                let pull = octocrab.pulls(&pull_request.owner.clone(), &pull_request.repo.clone());
+                let mut merge_call = pull
+                    .merge(pull_request.request)
+                    .method(octocrab_method(method));
+
+                let (title, message) = self.commit_text(pull_request, &pr).await?;
+                if let Some(title) = &title {
+                    merge_call = merge_call.title(title);
                 }
-                Action::Merge => {
-                    info!("Merging: {:?}", &pull_request.url);
-                    // TODO: Extract
-                    let pull =
-                        octocrab.pulls(&pull_request.owner.clone(), &pull_request.repo.clone());
-                    let mut merge_call = pull
-                        .merge(pull_request.request)
-                        .method(octocrab::params::pulls::MergeMethod::Squash);
-
-                    if let Some(title) = &self.configuration.merger.title {
-                        merge_call = merge_call.title(title);
-                    }
-
-                    if let Some(message) = &self.configuration.merger.message {
-                        merge_call = merge_call.message(message);
-                    }
-
-                    merge_call.send().await?;
-
-                    Some(pull_request.clone())
+
+                if let Some(message) = &message {
+                    merge_call = merge_call.message(message);
                 }
-            };
 
-            self.dispatch_message(action, &pull_request);
+                merge_call.send().await?;
 
-            if let Some(request) = to_remove {
-                self.requests_to_remove.push(request);
+                if options.delete_branch {
+                    Self::delete_branch(pull_request, &pr).await;
+                }
+
+                Some(pull_request.clone())
             }
+        };
+
+        self.dispatch_message(action, pull_request);
+
+        if let Some(request) = to_remove {
+            self.requests_to_remove.push(request);
         }
 
         Ok(())
     }
 
     pub fn dispatch_message(&self, action: Action, pull_request: &PullRequest) {
-        let option: &Option<Notification> = match action {
-            Action::NoOp => {
-                return;
-            }
-            Action::Merge => &self.configuration.notifier.merge,
-            Action::Update => &self.configuration.notifier.update,
-            Action::Pop => &self.configuration.notifier.pop,
+        if let Some((option, body)) = self.notification(&action, pull_request) {
+            Self::notify(option, body);
+        }
+    }
+
+    /// The notification for `action` on `pull_request` with its body, if any.
+    fn notification(
+        &self,
+        action: &Action,
+        pull_request: &PullRequest,
+    ) -> Option<(&Option<Notification>, String)> {
+        let notifier = &self.configuration.notifier;
+
+        if let Some((_, reason)) = self
+            .failed_checks
+            .iter()
+            .find(|(p, _)| p.same(pull_request))
+        {
+            let url = pull_request.url.as_deref().unwrap_or_default();
+            return Some((&notifier.checks, format!("Popped {}: {}", url, reason)));
+        }
+
+        let option = match action {
+            Action::NoOp => return None,
+            Action::Merge => &notifier.merge,
+            Action::Update => &notifier.update,
+            Action::Pop => &notifier.pop,
         };
 
+        Some((option, format!("Notification for {:?}", action)))
+    }
+
+    /// Shows `option` as a desktop notification, with `body` unless it has a message.
+    fn notify(option: &Option<Notification>, body: String) {
         if let Some(notification) = option {
             if !notification.enabled {
                 return;
@@ -98,53 +229,525 @@ impl Runner {
 
             let mut handler = builder
                 .summary(&notification.title)
-                .body(
-                    &notification
-                        .message
-                        .as_ref()
-                        .unwrap_or(&format!("Notification for {:?}", action)),
-                )
+                .body(notification.message.as_ref().unwrap_or(&body))
                 .timeout(Timeout::Milliseconds(6000));
 
             if let Some(icon) = &notification.icon {
                 handler = handler.icon(icon);
             }
 
-            handler.show().unwrap();
+            if let Err(err) = handler.show() {
+                warn!("Failed to show notification: {}", err);
+            }
         }
     }
 
-    async fn detect_action(&mut self, pull_request: &PullRequest) -> Result<Action> {
-        let octocrab = octocrab::instance();
-        let pr = octocrab
+    /// The merge method of the pull request, else of its repository in the configuration.
+    /// Falls back to a method the repository allows if the configured one is disabled there,
+    /// while a method given for the pull request fails it.
+    async fn merge_method(&self, pull_request: &PullRequest) -> Result<MergeMethod> {
+        let explicitly = pull_request.options.method.is_some();
+        let method = pull_request.options.method.unwrap_or_else(|| {
+            self.configuration
+                .merge_method(&pull_request.owner, &pull_request.repo)
+        });
+
+        let allowed = allowed_methods(octocrab::instance().as_ref(), pull_request).await?;
+        match allowed.choose(method, explicitly) {
+            Some(chosen) => {
+                if chosen != method {
+                    warn!(
+                        "{:?} does not allow {:?} merges, using {:?}",
+                        &pull_request.url, method, chosen
+                    );
+                }
+
+                Ok(chosen)
+            }
+            None if explicitly => bail!(
+                "{:?} does not allow {:?} merges as given when pushing it",
+                &pull_request.url,
+                method
+            ),
+            None => bail!("{:?} allows no merge method", &pull_request.url),
+        }
+    }
+
+    /// The title and message of the merge commit, rendered from the templates
+    /// given for the pull request or else the configured ones.
+    /// `None` leaves it to GitHub's default.
+    async fn commit_text(
+        &self,
+        pull_request: &PullRequest,
+        pr: &octocrab::models::pulls::PullRequest,
+    ) -> Result<(Option<String>, Option<String>)> {
+        let merger = &self.configuration.merger;
+        let options = &pull_request.options;
+        let title = options.title.as_ref().or(merger.title.as_ref());
+        let message = options.message.as_ref().or(merger.message.as_ref());
+
+        let uses_co_authors = title
+            .iter()
+            .chain(message.iter())
+            .any(|template| template::uses(template, "co_authors"));
+        let co_authors = if uses_co_authors {
+            Self::co_authors(pull_request, pr).await?
+        } else {
+            Vec::new()
+        };
+        let values = Values::new(pr, &co_authors);
+
+        let title = match title {
+            Some(title) => Some(template::render(title, &values)),
+            None if merger.title_with_number => {
+                Some(template::render("{title} (#{number})", &values))
+            }
+            None => None,
+        };
+        let message = message.map(|message| template::render(message, &values));
+
+        Ok((title, message))
+    }
+
+    /// The distinct authors of the commits in the pull request, other than its author,
+    /// as `name <email>`.
+    async fn co_authors(
+        pull_request: &PullRequest,
+        pr: &octocrab::models::pulls::PullRequest,
+    ) -> Result<Vec<String>> {
+        let route = format!(
+            "repos/{}/{}/pulls/{}/commits?per_page=100",
+            pull_request.owner, pull_request.repo, pull_request.request
+        );
+        let commits: Vec<serde_json::Value> = octocrab::instance().get(route, None::<&()>).await?;
+
+        let author = pr.user.as_ref().map(|user| user.login.as_str());
+        let mut co_authors = Vec::new();
+        for commit in &commits {
+            // Commits of unknown accounts have no login, which never matches the author.
+            let login = commit["author"]["login"].as_str();
+            if login.is_some() && login == author {
+                continue;
+            }
+
+            let name = commit["commit"]["author"]["name"].as_str();
+            let email = commit["commit"]["author"]["email"].as_str();
+            if let (Some(name), Some(email)) = (name, email) {
+                let co_author = format!("{} <{}>", name, email);
+                if !co_authors.contains(&co_author) {
+                    co_authors.push(co_author);
+                }
+            }
+        }
+
+        Ok(co_authors)
+    }
+
+    /// Deletes the head branch of a merged pull request, unless it lives in a fork.
+    async fn delete_branch(pull_request: &PullRequest, pr: &octocrab::models::pulls::PullRequest) {
+        let repository = format!("{}/{}", pull_request.owner, pull_request.repo);
+        let same_repository = pr
+            .head
+            .repo
+            .as_ref()
+            .and_then(|repo| repo.full_name.as_ref())
+            .map_or(false, |name| name.eq_ignore_ascii_case(&repository));
+
+        if !same_repository {
+            warn!(
+                "Not deleting the branch of {:?} from a fork",
+                &pull_request.url
+            );
+            return;
+        }
+
+        let branch = Reference::Branch(pr.head.ref_field.clone());
+        if let Err(err) = octocrab::instance()
+            .repos(&pull_request.owner, &pull_request.repo)
+            .delete_ref(&branch)
+            .await
+        {
+            warn!(
+                "Failed to delete the branch of {:?}: {}",
+                &pull_request.url, err
+            );
+        }
+    }
+
+    async fn detect_action(
+        &mut self,
+        pull_request: &PullRequest,
+    ) -> Result<(Action, octocrab::models::pulls::PullRequest)> {
+        let mut pr = Self::fetch(pull_request).await?;
+
+        for delay in &MERGEABILITY_BACKOFF {
+            if self::should_pop(&pr) || self::mergeability(&pr) != Mergeability::Pending {
+                break;
+            }
+
+            trace!(
+                "Mergeability of {:?} is pending, checking again in {:?}",
+                &pull_request.url,
+                delay
+            );
+            tokio::time::sleep(*delay).await;
+            pr = Self::fetch(pull_request).await?;
+        }
+
+        if self::should_pop(&pr) {
+            return Ok((Action::Pop, pr));
+        }
+
+        if self::mergeability(&pr) == Mergeability::Pending {
+            info!(
+                "Mergeability of {:?} is still pending, checking again next run",
+                &pull_request.url
+            );
+        }
+
+        // Without updates the branch is left for someone else to update.
+        let (action, state, reason) = self::assess(&pr, !pull_request.options.no_update);
+
+        // Repositories without branch protection report pending and failing checks as
+        // mergeable, with branch protection failing required checks block the pull request.
+        let inspect = matches!(state, State::ReadyToMerge | State::WaitingForChecks)
+            || matches!(pr.mergeable_state, Some(MergeableState::Blocked));
+        let (action, state, reason) = if inspect {
+            let required = self
+                .configuration
+                .required_checks(&pull_request.owner, &pull_request.repo);
+            // CI may not have reported on the commits added by the last update yet.
+            let expected = pull_request.state == State::Updating;
+            let octocrab = octocrab::instance();
+            let checks = checks(
+                octocrab.as_ref(),
+                pull_request,
+                &pr.head.sha,
+                required,
+                expected,
+            )
+            .await?;
+
+            apply_checks((action, state, reason), checks)
+        } else {
+            (action, state, reason)
+        };
+
+        if let (Action::Pop, Some(reason)) = (&action, &reason) {
+            warn!("Popping {:?}, {}", &pull_request.url, reason);
+            self.failed_checks
+                .push((pull_request.clone(), reason.clone()));
+        }
+        Self::set_state(pull_request, state, reason).await;
+
+        Ok((action, pr))
+    }
+
+    /// Updates the state of the queued pull request right away, so clients see it mid-run.
+    async fn set_state(pull_request: &PullRequest, state: State, reason: Option<String>) {
+        let mut queue = REPOS.lock().await;
+
+        // The pull request may have been popped while the queue was processed.
+        if let Some(queued) = queue.iter_mut().find(|queued| queued.same(pull_request)) {
+            queued.set_state(state, reason);
+        }
+    }
+
+    async fn fetch(pull_request: &PullRequest) -> Result<octocrab::models::pulls::PullRequest> {
+        let pr = octocrab::instance()
             .pulls(&pull_request.owner, &pull_request.repo)
             .media_type(octocrab::params::pulls::MediaType::Full)
             .get(pull_request.request)
             .await?;
 
-        if self::should_pop(&pr) {
-            return Ok(Action::Pop);
-        }
+        Ok(pr)
+    }
+
+    /// The pull requests merged or popped by the last `process`.
+    pub fn removed(&self) -> &[PullRequest] {
+        &self.requests_to_remove
+    }
+
+    /// The pull requests dropped by `cleanup` after failing too often
+    /// or as their checks failed, which aren't queued again from watched repositories.
+    pub fn dropped(&self) -> &[PullRequest] {
+        &self.dropped
+    }
 
-        if self::should_update(&pr) {
-            return Ok(Action::Update);
+    /// Applies the last `process` to the queue: removes merged and closed pull requests,
+    /// counts failures and drops the pull requests that reached `queue.max_failures`.
+    pub fn cleanup(&mut self, pull_requests: &mut Vec<PullRequest>) {
+        for pull_request in &self.requests_to_remove {
+            if let Some(index) = pull_requests.iter().position(|p| (*p).same(pull_request)) {
+                pull_requests.remove(index);
+            }
         }
 
-        if self::should_merge(&pr) {
-            return Ok(Action::Merge);
+        let max_failures = self.configuration.queue.max_failures;
+        self.dropped = record_failures(pull_requests, &self.outcomes, max_failures);
+
+        for pull_request in &self.dropped {
+            let error = pull_request.reason.as_deref().unwrap_or_default();
+            warn!(
+                "Dropping {:?} after {} failures: {}",
+                &pull_request.url, pull_request.failures, error
+            );
+            Self::notify(
+                &self.configuration.notifier.drop,
+                format!(
+                    "Dropped {} after {} failures: {}",
+                    pull_request.url.as_deref().unwrap_or_default(),
+                    pull_request.failures,
+                    error
+                ),
+            );
         }
 
-        Ok(Action::NoOp)
+        self.requests_to_remove.extend(self.dropped.iter().cloned());
+        // Already removed as popped.
+        self.dropped
+            .extend(self.failed_checks.iter().map(|(p, _)| p.clone()));
     }
+}
 
-    pub fn cleanup(&self, pull_requests: &mut Vec<PullRequest>) {
-        for pull_request in &self.requests_to_remove {
-            let index = pull_requests
-                .iter()
-                .position(|p| (*p).same(pull_request))
-                .unwrap();
+/// Applies the checks on the head commit to the `assessment` of a pull request.
+/// Failing checks pop it. Blocked pull requests stay blocked otherwise,
+/// as they may lack reviews as well.
+fn apply_checks(
+    assessment: (Action, State, Option<String>),
+    checks: Checks,
+) -> (Action, State, Option<String>) {
+    match checks {
+        Checks::Failed(names) => (
+            Action::Pop,
+            State::Failed,
+            Some(format!("checks failed: {}", names.join(", "))),
+        ),
+        _ if assessment.1 == State::Blocked => assessment,
+        Checks::Pending(names) if names.is_empty() => (
+            Action::NoOp,
+            State::WaitingForChecks,
+            Some("waiting for checks to start".to_string()),
+        ),
+        Checks::Pending(names) => (
+            Action::NoOp,
+            State::WaitingForChecks,
+            Some(format!("waiting for {}", names.join(", "))),
+        ),
+        Checks::Passed => (Action::Merge, State::ReadyToMerge, None),
+    }
+}
+
+/// The head of line among `heads` that `pull_request` waits for, if any.
+fn head_of_line<'a>(
+    heads: &'a [PullRequest],
+    pull_request: &PullRequest,
+) -> Option<&'a PullRequest> {
+    heads.iter().find(|head| head.same_repository(pull_request))
+}
 
-            pull_requests.remove(index);
+/// Counts consecutive failures on the queued pull requests and resets them on success,
+/// marking the failed ones as `State::Failed` with their error.
+/// Removes and returns the pull requests that failed `max_failures` times in a row.
+fn record_failures(
+    queue: &mut Vec<PullRequest>,
+    outcomes: &[(PullRequest, Option<String>)],
+    max_failures: u32,
+) -> Vec<PullRequest> {
+    for (pull_request, error) in outcomes {
+        let queued = match queue.iter_mut().find(|queued| queued.same(pull_request)) {
+            Some(queued) => queued,
+            None => continue,
+        };
+
+        match error {
+            Some(error) => {
+                queued.failures += 1;
+                queued.set_state(State::Failed, Some(error.clone()));
+            }
+            None => queued.failures = 0,
         }
     }
+
+    let (dropped, kept) = queue
+        .drain(..)
+        .partition(|queued: &PullRequest| queued.failures >= max_failures);
+    *queue = kept;
+
+    dropped
+}
+
+fn octocrab_method(method: MergeMethod) -> octocrab::params::pulls::MergeMethod {
+    match method {
+        MergeMethod::Merge => octocrab::params::pulls::MergeMethod::Merge,
+        MergeMethod::Squash => octocrab::params::pulls::MergeMethod::Squash,
+        MergeMethod::Rebase => octocrab::params::pulls::MergeMethod::Rebase,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepositorySettings;
+
+    #[test]
+    fn test_record_failures() {
+        let mut queue = vec![PullRequest::numbered(1), PullRequest::numbered(2)];
+        queue[1].failures = 1;
+        let outcomes = vec![
+            (PullRequest::numbered(1), Some("timed out".to_string())),
+            (PullRequest::numbered(2), Some("timed out".to_string())),
+            // Popped while the queue was processed.
+            (PullRequest::numbered(3), Some("timed out".to_string())),
+        ];
+
+        let dropped = record_failures(&mut queue, &outcomes, 2);
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].request, 2);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].failures, 1);
+        assert_eq!(queue[0].state, State::Failed);
+        assert_eq!(queue[0].reason, Some("timed out".to_string()));
+
+        record_failures(&mut queue, &[(PullRequest::numbered(1), None)], 2);
+        assert_eq!(queue[0].failures, 0);
+    }
+
+    #[test]
+    fn test_cleanup_dismisses_failed_checks() {
+        let mut runner = Runner {
+            configuration: Configuration::default(),
+            requests_to_remove: vec![PullRequest::numbered(1)],
+            outcomes: vec![
+                (PullRequest::numbered(1), None),
+                (PullRequest::numbered(2), None),
+            ],
+            dropped: Vec::new(),
+            failed_checks: vec![(PullRequest::numbered(1), "checks failed: build".into())],
+        };
+
+        let mut queue = vec![PullRequest::numbered(1), PullRequest::numbered(2)];
+        runner.cleanup(&mut queue);
+
+        assert_eq!(queue, vec![PullRequest::numbered(2)]);
+        assert_eq!(runner.removed(), [PullRequest::numbered(1)]);
+        // Dismissed by the agent, so it isn't queued again while its checks fail.
+        assert_eq!(runner.dropped(), [PullRequest::numbered(1)]);
+    }
+
+    #[test]
+    fn test_notification_for_failed_checks() {
+        let runner = Runner {
+            configuration: Configuration::default(),
+            requests_to_remove: Vec::new(),
+            outcomes: Vec::new(),
+            dropped: Vec::new(),
+            failed_checks: vec![(PullRequest::numbered(1), "checks failed: build".into())],
+        };
+
+        let (option, body) = runner
+            .notification(&Action::Pop, &PullRequest::numbered(1))
+            .unwrap();
+        assert!(std::ptr::eq(option, &runner.configuration.notifier.checks));
+        assert_eq!(
+            body,
+            "Popped https://github.com/Liberatys/rusty-merge/pull/1: checks failed: build"
+        );
+
+        let (option, _) = runner
+            .notification(&Action::Pop, &PullRequest::numbered(2))
+            .unwrap();
+        assert!(std::ptr::eq(option, &runner.configuration.notifier.pop));
+        assert!(runner
+            .notification(&Action::NoOp, &PullRequest::numbered(2))
+            .is_none());
+    }
+
+    #[test]
+    fn test_apply_checks() {
+        let ready = || (Action::Merge, State::ReadyToMerge, None);
+        let blocked = || {
+            (
+                Action::NoOp,
+                State::Blocked,
+                Some("missing reviews".to_string()),
+            )
+        };
+
+        assert!(matches!(
+            apply_checks(ready(), Checks::Pending(vec!["build".into()])),
+            (Action::NoOp, State::WaitingForChecks, Some(reason)) if reason == "waiting for build"
+        ));
+        assert!(matches!(
+            apply_checks(ready(), Checks::Passed),
+            (Action::Merge, State::ReadyToMerge, None)
+        ));
+        assert!(matches!(
+            apply_checks(blocked(), Checks::Failed(vec!["build".into()])),
+            (Action::Pop, State::Failed, Some(reason)) if reason == "checks failed: build"
+        ));
+        assert!(matches!(
+            apply_checks(blocked(), Checks::Passed),
+            (Action::NoOp, State::Blocked, Some(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_serial_repository_waits_for_head() -> Result<()> {
+        let runner = || Runner {
+            configuration: Configuration {
+                repositories: vec![RepositorySettings {
+                    name: "Liberatys/*".into(),
+                    method: None,
+                    serial: true,
+                    required_checks: Vec::new(),
+                }],
+                ..Configuration::default()
+            },
+            requests_to_remove: Vec::new(),
+            outcomes: Vec::new(),
+            dropped: Vec::new(),
+            failed_checks: Vec::new(),
+        };
+        let queue = vec![PullRequest::numbered(1), PullRequest::numbered(2)];
+
+        // The head is still queued after processing, #2 waits without an outcome.
+        let mut processed = Vec::new();
+        let mut waiting = runner();
+        waiting
+            .process_with(queue.clone(), |_, pull_request| {
+                processed.push(pull_request.request);
+                Box::pin(async { Ok(()) })
+            })
+            .await?;
+        assert_eq!(processed, [1]);
+        assert_eq!(waiting.outcomes.len(), 1);
+
+        // Once the head is merged, #2 is next in line.
+        let mut processed = Vec::new();
+        runner()
+            .process_with(queue, |runner, pull_request| {
+                processed.push(pull_request.request);
+                if pull_request.request == 1 {
+                    runner.requests_to_remove.push(pull_request.clone());
+                }
+                Box::pin(async { Ok(()) })
+            })
+            .await?;
+        assert_eq!(processed, [1, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_head_of_line() {
+        let heads = vec![PullRequest::new("Liberatys/rusty-merge#1".into()).unwrap()];
+
+        let waiting = PullRequest::new("liberatys/Rusty-Merge#2".into()).unwrap();
+        assert_eq!(head_of_line(&heads, &waiting), Some(&heads[0]));
+
+        let other = PullRequest::new("octocat/hello#2".into()).unwrap();
+        assert_eq!(head_of_line(&heads, &other), None);
+    }
 }
@@ -1,23 +1,65 @@
+use super::Action;
+use crate::pull_request::State;
 use octocrab::models::pulls::MergeableState;
 use octocrab::models::pulls::PullRequest;
 use octocrab::models::IssueState;
 
-pub fn should_pop(pull_request: &PullRequest) -> bool {
-    pull_request.state == Some(IssueState::Closed)
+/// Whether a pull request can be merged, as far as GitHub knows yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mergeability {
+    /// GitHub computes mergeability in the background after a push,
+    /// until then it is unknown.
+    Pending,
+    Mergeable,
+    Conflicting,
 }
 
-pub fn should_update(pull_request: &PullRequest) -> bool {
-    pull_request.mergeable_state == Some(MergeableState::Behind)
+pub fn mergeability(pull_request: &PullRequest) -> Mergeability {
+    match pull_request.mergeable {
+        None => Mergeability::Pending,
+        Some(true) => Mergeability::Mergeable,
+        Some(false) => Mergeability::Conflicting,
+    }
 }
 
-pub fn should_merge(pull_request: &PullRequest) -> bool {
-    if pull_request.mergeable.unwrap() == false {
-        return false;
-    }
+pub fn should_pop(pull_request: &PullRequest) -> bool {
+    pull_request.state == Some(IssueState::Closed)
+}
+
+/// What to do with an open pull request, along with the state it is in and why.
+/// Pull requests behind their base branch are only updated with `update`.
+pub fn assess(pull_request: &PullRequest, update: bool) -> (Action, State, Option<String>) {
+    let waiting = |state: State, reason: &str| (Action::NoOp, state, Some(reason.to_string()));
 
-    if pull_request.mergeable_state == Some(MergeableState::Clean) {
-        true
-    } else {
-        false
+    match mergeability(pull_request) {
+        Mergeability::Pending => waiting(State::Queued, "GitHub is computing mergeability"),
+        Mergeability::Conflicting => waiting(State::Conflicting, "conflicts with the base branch"),
+        Mergeability::Mergeable => match &pull_request.mergeable_state {
+            Some(MergeableState::Clean) => (Action::Merge, State::ReadyToMerge, None),
+            Some(MergeableState::Behind) if update => (
+                Action::Update,
+                State::Updating,
+                Some("behind the base branch".to_string()),
+            ),
+            Some(MergeableState::Behind) => waiting(
+                State::Blocked,
+                "behind the base branch, which it is not updated with",
+            ),
+            Some(MergeableState::Unstable) => {
+                waiting(State::WaitingForChecks, "checks are pending or failing")
+            }
+            Some(MergeableState::Blocked) => waiting(
+                State::Blocked,
+                "blocked by branch protection, e.g. missing reviews or required checks",
+            ),
+            Some(MergeableState::Draft) => waiting(State::Blocked, "draft"),
+            Some(MergeableState::Dirty) => {
+                waiting(State::Conflicting, "conflicts with the base branch")
+            }
+            Some(MergeableState::Unknown) | None => {
+                waiting(State::Queued, "GitHub is computing mergeability")
+            }
+            Some(state) => waiting(State::Blocked, &format!("mergeable state is {:?}", state)),
+        },
     }
 }
@@ -0,0 +1,163 @@
+use crate::pull_request::PullRequest;
+use crate::util::unix_timestamp;
+use anyhow::Result;
+use directories_next::BaseDirs;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+static QUEUE_FILE: &str = "queue.json";
+static DISMISSED_FILE: &str = "dismissed.json";
+
+/// Loads the persisted queue, returning an empty queue if none was stored yet.
+///
+/// A file that can't be parsed is moved aside so the agent can still start;
+/// its content is kept next to the queue file for manual inspection.
+pub fn load() -> Result<Vec<PullRequest>> {
+    load_from(&state_path(QUEUE_FILE))
+}
+
+/// Atomically replaces the persisted queue with `pull_requests`.
+pub fn store(pull_requests: &[PullRequest]) -> Result<()> {
+    store_to(&state_path(QUEUE_FILE), pull_requests)
+}
+
+/// Stores the queue and logs on failure.
+/// Used by the agent where a failed write must not abort the request itself.
+pub fn persist(pull_requests: &[PullRequest]) {
+    if let Err(err) = store(pull_requests) {
+        log::error!("Failed to persist queue: {}", err);
+    }
+}
+
+/// Loads the pull requests removed from the queue by hand,
+/// which are not queued again when found in a watched repository.
+pub fn load_dismissed() -> Result<Vec<PullRequest>> {
+    load_from(&state_path(DISMISSED_FILE))
+}
+
+/// Stores the dismissed pull requests and logs on failure.
+pub fn persist_dismissed(pull_requests: &[PullRequest]) {
+    if let Err(err) = store_to(&state_path(DISMISSED_FILE), pull_requests) {
+        log::error!("Failed to persist dismissed pull requests: {}", err);
+    }
+}
+
+fn load_from(path: &Path) -> Result<Vec<PullRequest>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(path)?;
+
+    match serde_json::from_str(&content) {
+        Ok(pull_requests) => Ok(pull_requests),
+        Err(err) => {
+            let backup = corrupt_path(path);
+            log::warn!(
+                "Queue file {} is corrupt ({}), moving it to {}",
+                path.display(),
+                err,
+                backup.display()
+            );
+            fs::rename(path, backup)?;
+
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn store_to(path: &Path, pull_requests: &[PullRequest]) -> Result<()> {
+    let content = serde_json::to_string_pretty(pull_requests)?;
+
+    if let Some(prefix) = path.parent() {
+        fs::create_dir_all(prefix)?;
+    }
+
+    // Write into a sibling file first and rename it over the old queue,
+    // so a crash mid-write never leaves a truncated queue behind.
+    let temporary = path.with_extension("json.tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+fn corrupt_path(path: &Path) -> PathBuf {
+    path.with_extension(format!("json.corrupt-{}", unix_timestamp()))
+}
+
+fn state_path(file: &str) -> PathBuf {
+    let package_name = env!("CARGO_PKG_NAME");
+
+    let mut path = BaseDirs::new().unwrap().data_dir().to_path_buf();
+    path.push(package_name);
+    path.push(file);
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn test_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "{}-state-test-{}-{}",
+            env!("CARGO_PKG_NAME"),
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        path.push("queue.json");
+
+        path
+    }
+
+    #[test]
+    fn test_load_missing() -> Result<()> {
+        let path = test_path("missing");
+
+        assert_eq!(load_from(&path)?, Vec::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_and_load() -> Result<()> {
+        let path = test_path("roundtrip");
+        let pull_requests = vec![PullRequest::new(
+            "https://github.com/Liberatys/rusty-merge/pull/400".into(),
+        )?];
+
+        store_to(&path, &pull_requests)?;
+
+        assert_eq!(load_from(&path)?, pull_requests);
+        assert_eq!(path.with_extension("json.tmp").exists(), false);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_corrupt() -> Result<()> {
+        let path = test_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, "[{\"url\": ")?;
+
+        assert_eq!(load_from(&path)?, Vec::new());
+        assert_eq!(path.exists(), false);
+        assert_eq!(fs::read_dir(path.parent().unwrap())?.count(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap())?;
+
+        Ok(())
+    }
+}
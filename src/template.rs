@@ -0,0 +1,196 @@
+use anyhow::{bail, Result};
+use octocrab::models::pulls::PullRequest;
+
+/// The placeholders known to merge commit templates.
+const PLACEHOLDERS: &[&str] = &[
+    "title",
+    "number",
+    "author",
+    "head_ref",
+    "base_ref",
+    "url",
+    "co_authors",
+];
+
+/// The values filled into the placeholders of a merge commit template.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Values {
+    pub title: String,
+    pub number: u64,
+    pub author: String,
+    pub head_ref: String,
+    pub base_ref: String,
+    pub url: String,
+    /// `Co-authored-by: name <email>` trailers, one per line.
+    pub co_authors: String,
+}
+
+impl Values {
+    pub fn new(pull_request: &PullRequest, co_authors: &[String]) -> Self {
+        Self {
+            title: pull_request.title.clone().unwrap_or_default(),
+            number: pull_request.number,
+            author: pull_request
+                .user
+                .as_ref()
+                .map(|user| user.login.clone())
+                .unwrap_or_default(),
+            head_ref: pull_request.head.ref_field.clone(),
+            base_ref: pull_request.base.ref_field.clone(),
+            url: pull_request
+                .html_url
+                .as_ref()
+                .map(|url| url.to_string())
+                .unwrap_or_default(),
+            co_authors: co_authors
+                .iter()
+                .map(|co_author| format!("Co-authored-by: {}", co_author))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        }
+    }
+
+    fn get(&self, placeholder: &str) -> Option<String> {
+        match placeholder {
+            "title" => Some(self.title.clone()),
+            "number" => Some(self.number.to_string()),
+            "author" => Some(self.author.clone()),
+            "head_ref" => Some(self.head_ref.clone()),
+            "base_ref" => Some(self.base_ref.clone()),
+            "url" => Some(self.url.clone()),
+            "co_authors" => Some(self.co_authors.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `template` refers to `placeholder`, to skip fetching values that aren't used.
+pub fn uses(template: &str, placeholder: &str) -> bool {
+    template.contains(&format!("{{{}}}", placeholder))
+}
+
+/// Fills the `{placeholder}`s of `template`, where `{{` and `}}` stand for literal braces.
+pub fn render(template: &str, values: &Values) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            rendered.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        // A lone `}` or unknown placeholder is kept as is.
+        let value = match rest.strip_prefix('{') {
+            Some(placeholder) => placeholder.find('}').and_then(|end| {
+                values
+                    .get(&placeholder[..end])
+                    .map(|value| (end + 1, value))
+            }),
+            None => None,
+        };
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Checks `template` for unknown placeholders and unbalanced braces.
+pub fn validate(template: &str) -> Result<()> {
+    let mut rest = template;
+
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        rest = &rest[start..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            rest = &rest[2..];
+            continue;
+        }
+
+        if rest.starts_with('}') {
+            bail!(
+                "unmatched }} in template {}, use }}}} for a literal }}",
+                template
+            );
+        }
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => bail!(
+                "unmatched {{ in template {}, use {{{{ for a literal {{",
+                template
+            ),
+        };
+
+        let placeholder = &rest[1..end];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            bail!(
+                "unknown placeholder {{{}}} in template {}, expected one of {}",
+                placeholder,
+                template,
+                PLACEHOLDERS.join(", ")
+            );
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> Values {
+        Values {
+            title: "Bump serde".into(),
+            number: 400,
+            author: "dependabot[bot]".into(),
+            head_ref: "dependabot/cargo/serde".into(),
+            base_ref: "main".into(),
+            url: "https://github.com/Liberatys/rusty-merge/pull/400".into(),
+            co_authors: "Co-authored-by: Octo Cat <octocat@example.com>".into(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("{title} (#{number}) by {author}", &values()),
+            "Bump serde (#400) by dependabot[bot]"
+        );
+        assert_eq!(
+            render("{head_ref} into {base_ref}\n\n{url}", &values()),
+            "dependabot/cargo/serde into main\n\nhttps://github.com/Liberatys/rusty-merge/pull/400"
+        );
+        assert_eq!(
+            render("{{title}} {unknown} {", &values()),
+            "{title} {unknown} {"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("{title} (#{number})\n\n{co_authors}").is_ok());
+        assert!(validate("{{literal}}").is_ok());
+
+        assert!(validate("{unknown}").is_err());
+        assert!(validate("{title").is_err());
+        assert!(validate("title}").is_err());
+    }
+}